@@ -0,0 +1,50 @@
+// Used for drawing lines and other things on top of the terrain (like the block outline and the crosshair).
+struct WorldVertexInput {
+    @location(0) abs_position: vec3<i32>,
+    @location(1) rel_position: vec3<f32>,
+    @location(2) colour: vec4<f32>,
+};
+
+struct ScreenVertexInput {
+    @location(0) screen_position: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) colour: vec4<f32>,
+};
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    position_abs: vec3<i32>,
+    position_rel: vec3<f32>,
+    sun_normal: vec3<f32>,
+    sun_intensity: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+//Vertices positioned in the world the same way as the terrain.
+@vertex
+fn vs_world(in: WorldVertexInput) -> VertexOutput {
+    let camera_relative = vec3<f32>(in.abs_position - camera.position_abs) + in.rel_position - camera.position_rel;
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4(camera_relative, 1.0);
+    out.colour = in.colour;
+    return out;
+}
+
+//Vertices already in screen space.
+@vertex
+fn vs_screen(in: ScreenVertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(in.screen_position, 0.0, 1.0);
+    out.colour = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.colour;
+}
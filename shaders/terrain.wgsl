@@ -1,9 +1,8 @@
 // Vertex shader
 struct VertexInput {
-    @location(0) abs_position: vec3<i32>,
-    @location(1) rel_position: vec3<f32>,
-    @location(2) colour: vec4<f32>,
-    @location(3) normal: vec3<f32>
+    @location(0) data: u32, //Packed position, side and block (see ChunkVertex).
+    @location(1) chunk_position: vec3<i32>, //Per chunk.
+    @location(2) chunk_scale: u32, //Per chunk. More than 1 for LOD tiles.
 };
 
 
@@ -27,28 +26,50 @@ struct CameraFragmentUniform {
     sun_intensity: f32
 }
 
+struct BlockPaletteUniform {
+    colours: array<vec4<f32>, 8>, //Indexed by block.
+}
+
 @group(0) @binding(0) // 1.
 var<uniform> camera: CameraUniform;
 
 @group(1) @binding(0) //don't know if this will work.
 var<uniform> camera_fragment: CameraFragmentUniform;
 
+@group(2) @binding(0)
+var<uniform> palette: BlockPaletteUniform;
+
 
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
-    let camera_relative = vec3<f32>(in.abs_position - camera.position_abs) + in.rel_position - camera.position_rel;
+    //Unpack the vertex.
+    let rel_position = vec3<f32>(vec3<u32>(in.data, in.data >> 6u, in.data >> 12u) & vec3<u32>(63u)) * f32(in.chunk_scale);
+    let side = (in.data >> 18u) & 7u;
+    let block = min((in.data >> 21u) & 255u, 7u);
+    var normals = array<vec3<f32>, 6>( //In BlockSide order.
+        vec3<f32>(1.0, 0.0, 0.0),
+        vec3<f32>(-1.0, 0.0, 0.0),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(0.0, -1.0, 0.0),
+        vec3<f32>(0.0, 0.0, 1.0),
+        vec3<f32>(0.0, 0.0, -1.0),
+    );
+    let normal = normals[side];
+    let block_colour = palette.colours[block];
+
+    let camera_relative = vec3<f32>(in.chunk_position - camera.position_abs) + rel_position - camera.position_rel;
     var out: VertexOutput;
     out.clip_position = camera.view_proj * vec4(camera_relative, 1.0);
-    let dot_product = dot(in.normal, camera.sun_normal);
+    let dot_product = dot(normal, camera.sun_normal);
     // let diffuse_level = clamp((dot_product+1.0)/2.0, 0.1, 1.0) * camera.sun_intensity;
     let diffuse_level = clamp(clamp(dot_product/2.0 + 0.5, 0.0, 1.0) * camera.sun_intensity + 0.2, 0.0, 1.0);
 
     //https://math.stackexchange.com/questions/13261/how-to-get-a-reflection-vector
-    let reflection = camera_relative - (2.0 * dot(camera_relative, in.normal) * in.normal);
+    let reflection = camera_relative - (2.0 * dot(camera_relative, normal) * normal);
 
     // let rgba = pow(in.colour.xyz * diffuse_level, vec3<f32>(2.2, 2.2, 2.2));
-    let rgba = in.colour.xyz * diffuse_level;
-    out.colour = vec4(rgba, in.colour.w);
+    let rgba = block_colour.xyz * diffuse_level;
+    out.colour = vec4(rgba, block_colour.w);
 
     // let colour_unclamped = vec4<f32>(reflection/128.0, 1.0);
     // out.colour = clamp(colour_unclamped, vec4<f32>(0.0), vec4<f32>(1.0));
@@ -0,0 +1,142 @@
+use crate::world::{load_area::{LoadArea, LoadShape}, mesher::MeshAlgorithm, lod::MAX_LOD_LEVELS, memory_budget::MemoryBudget};
+
+const MB: usize = 1024 * 1024;
+
+///Settings that can be changed from the command line (like `--workers 4`).
+#[derive(Debug, Clone)]
+pub struct GameConfig {
+  pub worker_threads: usize,
+  pub load_area: LoadArea,
+  pub mesh_algorithm: MeshAlgorithm,
+  pub lod_levels: u8, //How many rings of LOD tiles are drawn past the loaded chunks.
+  pub chunk_cache_size: usize, //In bytes.
+  pub memory_budget: MemoryBudget, //The render distance is reduced if the loaded chunks use more than this.
+}
+
+impl Default for GameConfig {
+  fn default() -> Self {
+    let cpu_count = num_cpus::get();
+    Self {
+      worker_threads: if cpu_count <= 2 {1} else {cpu_count - 2}, //Leave some threads for rendering and ticking.
+      load_area: LoadArea {
+        shape: LoadShape::Cylinder,
+        radius: 8,
+        vertical_radius: 6
+      },
+      mesh_algorithm: MeshAlgorithm::Simple,
+      lod_levels: MAX_LOD_LEVELS,
+      chunk_cache_size: 128 * MB,
+      memory_budget: MemoryBudget {
+        blocks: 1024 * MB,
+        meshes: 256 * MB //Clamped to what the GPU can fit in the mesh arena.
+      },
+    }
+  }
+}
+
+impl GameConfig {
+  pub fn from_args() -> Self {
+    Self::parse(std::env::args().skip(1))
+  }
+
+  ///Reads the options from a list of arguments. Invalid options are ignored (with a warning).
+  ///Flags (like `--greedy`) don't take a value. Unknown options don't either, so they can't swallow the option after them.
+  fn parse<I: Iterator<Item = String>>(mut args: I) -> Self {
+    let mut config = Self::default();
+
+    while let Some(arg) = args.next() {
+      //Gets the function that applies the option's value, or None if it can't be parsed.
+      let apply: fn(&mut Self, &str) -> Option<()> = match arg.as_str() {
+        "--greedy" => {
+          config.mesh_algorithm = MeshAlgorithm::Greedy;
+          continue;
+        },
+        "--workers" => |config, v| parse_value(v).filter(|v| *v > 0).map(|v| config.worker_threads = v),
+        "--render-distance" => |config, v| parse_value(v).map(|v| config.load_area.radius = v),
+        "--load-shape" => |config, v| parse_value(v).map(|v| config.load_area.shape = v),
+        "--vertical-distance" => |config, v| parse_value(v).map(|v| config.load_area.vertical_radius = v),
+        "--chunk-cache" => |config, v| parse_value::<usize>(v).map(|v| config.chunk_cache_size = v * MB),
+        "--block-budget" => |config, v| parse_value::<usize>(v).map(|v| config.memory_budget.blocks = v * MB),
+        "--mesh-budget" => |config, v| parse_value::<usize>(v).map(|v| config.memory_budget.meshes = v * MB),
+        "--lod-levels" => |config, v| parse_value(v).filter(|v| *v <= MAX_LOD_LEVELS).map(|v| config.lod_levels = v),
+        _ => {
+          eprintln!("Unknown option: {}", arg);
+          continue;
+        }
+      };
+
+      let value = args.next();
+      if value.as_deref().and_then(|value| apply(&mut config, value)).is_none() {
+        eprintln!("Invalid value for {}: {:?}", arg, value.unwrap_or_default());
+      }
+    }
+
+    config
+  }
+}
+
+fn parse_value<T: std::str::FromStr>(value: &str) -> Option<T> {
+  value.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::world::{load_area::LoadShape, mesher::MeshAlgorithm};
+
+  use super::GameConfig;
+
+  fn parse(args: &[&str]) -> GameConfig {
+    GameConfig::parse(args.iter().map(|arg| arg.to_string()))
+  }
+
+  #[test]
+  fn test_parse_workers() {
+    assert_eq!(parse(&["--workers", "3"]).worker_threads, 3);
+
+    let default_workers = GameConfig::default().worker_threads;
+    assert_eq!(parse(&["--workers", "0"]).worker_threads, default_workers);
+    assert_eq!(parse(&["--workers", "lots"]).worker_threads, default_workers);
+  }
+
+  #[test]
+  fn test_parse_load_area() {
+    let config = parse(&["--load-shape", "Sphere", "--render-distance", "12", "--vertical-distance", "3"]);
+    assert_eq!(config.load_area.shape, LoadShape::Sphere);
+    assert_eq!(config.load_area.radius, 12);
+    assert_eq!(config.load_area.vertical_radius, 3);
+  }
+
+  #[test]
+  fn test_parse_chunk_cache() {
+    assert_eq!(parse(&["--chunk-cache", "64"]).chunk_cache_size, 64 * 1024 * 1024);
+    assert_eq!(parse(&["--chunk-cache", "0"]).chunk_cache_size, 0);
+  }
+
+  #[test]
+  fn test_parse_memory_budget() {
+    let config = parse(&["--block-budget", "256", "--mesh-budget", "0"]);
+    assert_eq!(config.memory_budget.blocks, 256 * 1024 * 1024);
+    assert_eq!(config.memory_budget.meshes, 0);
+    assert_eq!(parse(&["--mesh-budget", "lots"]).memory_budget.meshes, GameConfig::default().memory_budget.meshes);
+  }
+
+  #[test]
+  fn test_parse_lod_levels() {
+    assert_eq!(parse(&["--lod-levels", "0"]).lod_levels, 0);
+    assert_eq!(parse(&["--lod-levels", "9"]).lod_levels, GameConfig::default().lod_levels);
+  }
+
+  #[test]
+  fn test_unknown_option_keeps_next() {
+    let config = parse(&["--foo", "--workers", "3", "--bar"]);
+    assert_eq!(config.worker_threads, 3);
+  }
+
+  #[test]
+  fn test_parse_flag() {
+    assert_eq!(parse(&[]).mesh_algorithm, MeshAlgorithm::Simple);
+    let config = parse(&["--greedy", "--workers", "2"]);
+    assert_eq!(config.mesh_algorithm, MeshAlgorithm::Greedy);
+    assert_eq!(config.worker_threads, 2);
+  }
+}
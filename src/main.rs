@@ -1,13 +1,14 @@
 use std::{sync::{mpsc::{channel, Receiver}, Arc, Mutex}, thread, time::{Duration, Instant}};
 
 use winit::{dpi::PhysicalPosition, event::{DeviceEvent, ElementState, Event, VirtualKeyCode, WindowEvent}, event_loop::{ControlFlow, EventLoop}, window::WindowBuilder};
-use world::{chunk_worker_pool, chunk::Chunk};
+use world::{chunk_worker_pool::ChunkWorkerPool, chunk::Chunk, chunkedterrain::TerrainSettings, chunk_cache::ChunkCache};
 
-use crate::{renderer::Renderer, world::World};
+use crate::{renderer::Renderer, world::World, config::GameConfig};
 
 mod renderer;
 mod world;
 mod util;
+mod config;
 
 pub type ArcWorld = Arc<Mutex<World>>;
 
@@ -19,36 +20,26 @@ fn main() {
 }
 
 async fn run() {
+  let mut config = GameConfig::from_args();
   let event_loop = EventLoop::new();
   let window = WindowBuilder::new()
     .with_title("DomCraft [INDEV]").build(&event_loop).expect("Failed to create window!");
   let mut renderer = Renderer::new(&window).await.unwrap();
 
-  let (worker_tx, worker_rx) = channel();
-  let rx_arc = Arc::new(Mutex::new(worker_rx));
-  let (device, queue) = renderer.get_device_queue();
-  let cpu_count = num_cpus::get();
-  let worker_thread_count = if cpu_count <= 2 {
-    1
-  } else {
-    cpu_count - 2
-  };
-  for i in 0..worker_thread_count { //Spawn worker threads.
-    let (device, queue, rx_arc) = (
-      device.clone(),
-      queue.clone(),
-      rx_arc.clone()
-    );
-    thread::Builder::new().name(format!("Worker #{}", i)).spawn(move || {
-      chunk_worker_pool::run_worker_thread(device, queue, rx_arc)
-    }).unwrap();
-  }
+  config.memory_budget.clamp_meshes(renderer.get_max_mesh_memory());
+  let worker_pool = ChunkWorkerPool::new(config.worker_threads, config.mesh_algorithm, renderer.get_mesh_upload_sender());
 
   //Spawn chunk GC thread.
   let (gc_tx, gc_rx) = channel();
   thread::Builder::new().name("GC Thread".to_string()).spawn(move || gc_thread(gc_rx)).unwrap();
 
-  let world = Arc::new(Mutex::new(World::new(worker_tx, gc_tx)));
+  let terrain_settings = TerrainSettings {
+    load_area: config.load_area,
+    lod_levels: config.lod_levels,
+    chunk_cache: ChunkCache::new(config.chunk_cache_size),
+    memory_budget: config.memory_budget
+  };
+  let world = Arc::new(Mutex::new(World::new(worker_pool.get_task_queue(), gc_tx, terrain_settings, renderer.get_mesh_memory_counter())));
 
   renderer.bind_world(world.clone());
 
@@ -62,6 +53,7 @@ async fn run() {
   window.set_cursor_visible(false);
 
   let mut is_focused = true;
+  let mut worker_pool = Some(worker_pool); //Taken when the event loop is destroyed.
 
   window.focus_window();
   event_loop.run(move |evt, _, ctrl| {
@@ -92,6 +84,12 @@ async fn run() {
               _ => ()
             }
           },
+          WindowEvent::MouseInput { device_id: _, state, button, .. } => {
+            let mut world = world.lock().unwrap();
+            if !world.is_mouse_unlocked() || *state == ElementState::Released { //Don't break blocks when clicking on the debug menu.
+              world.mouse_button_update(*button, *state == ElementState::Pressed);
+            }
+          },
           WindowEvent::Focused(f) => {
             is_focused = *f;
           }
@@ -121,6 +119,11 @@ async fn run() {
       Event::MainEventsCleared => {
         window.request_redraw()
       }
+      Event::LoopDestroyed => {
+        if let Some(worker_pool) = worker_pool.take() {
+          worker_pool.shutdown();
+        }
+      }
       _ => (),
     };
 
@@ -1,24 +1,27 @@
 mod texture;
 pub mod buffer;
+pub mod mesh_uploader;
+pub mod mesh_arena;
+mod culling;
 
-use std::{borrow::Cow, mem::size_of, sync::Arc, time::{Duration, Instant}};
+use std::{borrow::Cow, collections::HashMap, mem::size_of, sync::Arc, time::{Duration, Instant}};
 
 use bytemuck_derive::{Pod, Zeroable};
-use cgmath::{num_traits::Pow, Matrix4, SquareMatrix};
+use cgmath::{num_traits::Pow, EuclideanSpace, Matrix4, SquareMatrix, Vector3};
 use circular_buffer::CircularBuffer;
 use imgui::{Context, FontSource};
 use itertools::Itertools;
 use wgpu::{
-  Backends, BlendState, BufferDescriptor, Color, ColorTargetState, ColorWrites, CommandEncoder, CommandEncoderDescriptor, CompareFunction, DepthBiasState, DepthStencilState, Device, DeviceDescriptor, Face, FragmentState, FrontFace, IndexFormat, Instance, InstanceDescriptor, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor, PolygonMode, PowerPreference, PrimitiveState, PrimitiveTopology, Queue, RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, RequestAdapterOptions, ShaderModuleDescriptor, ShaderSource, StencilState, StoreOp, Surface, SurfaceConfiguration, TextureUsages, TextureView, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode
+  Backends, BlendComponent, BlendFactor, BlendOperation, BlendState, BufferDescriptor, Color, ColorTargetState, ColorWrites, CommandEncoder, CommandEncoderDescriptor, CompareFunction, DepthBiasState, DepthStencilState, Device, DeviceDescriptor, Face, Features, FragmentState, FrontFace, IndexFormat, Instance, InstanceDescriptor, LoadOp, MultisampleState, Operations, PipelineLayout, PipelineLayoutDescriptor, PolygonMode, PowerPreference, PrimitiveState, PrimitiveTopology, Queue, RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, RequestAdapterOptions, ShaderModule, ShaderModuleDescriptor, ShaderSource, StencilState, StoreOp, Surface, SurfaceConfiguration, TextureFormat, TextureUsages, TextureView, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState, VertexStepMode, util::DrawIndexedIndirect
 };
 
 use winit::{window::Window, dpi::PhysicalSize};
 
-use crate::{renderer::{buffer::{GenericBufferType, UniformBufferUsage}, texture::Texture}, util::FPVector, world::chunk::ChunkVertex, ArcWorld};
+use crate::{renderer::{buffer::{GenericBufferType, UniformBufferUsage}, texture::Texture}, util::FPVector, world::{chunkedterrain::{ChunkedTerrain, CHUNK_SIZE_I32}, chunk_state::ChunkStateStage, pipeline_stats::{histogram_bucket_limit, PipelineSnapshot, TaskKind, HISTOGRAM_BUCKETS}, memory_budget::{MemoryBudget, MemoryUsage}, mesher::ChunkVertex, visibility::{ChunkConnectivity, find_visible_chunks}, block::Block, edit::{Region, RegionOperation}, clipboard::PasteTransform, load_area::{LoadArea, LoadShape}, WorldCommand}, ArcWorld};
 
 use imgui_winit_support::{WinitPlatform, HiDpiMode};
 
-use self::buffer::{ArrayBuffer, UniformBuffer};
+use self::{buffer::{ArrayBuffer, UniformBuffer}, mesh_uploader::{MeshUploader, MeshUploadSender}, mesh_arena::{MeshArena, MeshMemoryCounter}, culling::Frustum};
 
 const FPS_ROLLING_AVG: usize = 8; //remember to change both at the same time
 const FPS_ROLLING_AVG_F32: f32 = 8.0;
@@ -32,10 +35,22 @@ pub struct Renderer {
   camera_fragment_buffer: UniformBuffer<CameraFragmentUniform>,
   depth_texture: Texture,
   terrain_pipeline: RenderPipeline,
+  chunk_origin_buffer: ArrayBuffer<ChunkOrigin>,
+  draw_indirect_buffer: ArrayBuffer<u8>, //DrawIndexedIndirect for every chunk drawn (only used with multi_draw).
+  mesh_arena: MeshArena,
+  multi_draw: bool,
+  palette_buffer: UniformBuffer<BlockPaletteUniform>,
   sky_pipeline: RenderPipeline,
   sky_vertex_buffer: ArrayBuffer<SkyVertex>,
   sky_camera_buffer: UniformBuffer<SkyCameraUniform>,
   sky_fragment_buffer: UniformBuffer<SkyFragmentUniform>,
+  outline_pipeline: RenderPipeline,
+  outline_vertex_buffer: ArrayBuffer<OverlayVertex>,
+  ghost_pipeline: RenderPipeline,
+  ghost_vertex_buffer: ArrayBuffer<OverlayVertex>,
+  crosshair_pipeline: RenderPipeline,
+  crosshair_vertex_buffer: ArrayBuffer<SkyVertex>,
+  mesh_uploader: MeshUploader,
   size: PhysicalSize<u32>,
   world: Option<ArcWorld>,
   imgui: RendererImgui,
@@ -47,11 +62,46 @@ pub struct RendererImgui {
   ui: Context,
   renderer: imgui_wgpu::Renderer,
   platform: WinitPlatform,
+  edit_menu: EditMenuState,
+  debug_menu: DebugMenuState,
 }
 
 struct ImguiData {
   pub fps: Option<f32>,
   pub player_pos: FPVector,
+  pub selection_corners: [Option<Vector3<i32>>; 2],
+  pub selected_block: Block,
+  pub history: HistoryInfo,
+  pub clipboard_size: Option<Vector3<i32>>,
+  pub paste_transform: PasteTransform,
+  pub load_area: LoadArea,
+  pub chunks_drawn: usize,
+  pub chunks_culled: usize, //Outside the view.
+  pub chunks_occluded: usize, //Hidden by other chunks.
+  pub lod_tiles_drawn: usize,
+  pub mesh_memory: (u64, u64), //Bytes used by meshes and the size of the mesh arena.
+  pub chunk_cache: (usize, usize), //Chunks in the unloaded chunk cache and the bytes they use.
+  pub pipeline: PipelineSnapshot,
+  pub memory: (MemoryUsage, MemoryBudget, u32), //What the loaded chunks use, the budget, and the render distance that fits in it.
+}
+
+///Sizes of the edit history for the world edit menu.
+struct HistoryInfo {
+  undo_count: usize,
+  redo_count: usize,
+  memory_used: usize,
+  memory_cap: usize,
+}
+
+///UI state for the world edit menu that needs to be kept between frames.
+struct EditMenuState {
+  replace_block: usize, //Index in Block::ALL.
+}
+
+///UI state for the debug menu that needs to be kept between frames.
+#[derive(Default)]
+struct DebugMenuState {
+  pending_load_area: Option<LoadArea>, //Set while a load area slider is being dragged.
 }
 
 //Modified from https://sotrh.github.io/learn-wgpu/
@@ -69,7 +119,11 @@ impl Renderer {
       ..Default::default()
     }).await.ok_or(RendererCreateError::NoDeviceFound)?;
 
+    //Chunks can all be drawn with one call if the GPU supports it. The instance index picks the chunk origin, so it needs first instance support too.
+    let multi_draw_features = Features::MULTI_DRAW_INDIRECT | Features::INDIRECT_FIRST_INSTANCE;
+    let multi_draw = adapter.features().contains(multi_draw_features);
     let (device, queue) = adapter.request_device(&DeviceDescriptor {
+      features: if multi_draw {multi_draw_features} else {Features::empty()},
       ..Default::default() //TODO fix.
     }, None).await.map_err(|_| RendererCreateError::RequestDeviceError)?;
 
@@ -97,6 +151,9 @@ impl Renderer {
 
 
     println!("Using {} for rendering.", adapter.get_info().name);
+    if !multi_draw {
+      println!("Multi draw indirect isn't supported, so chunks will be drawn one at a time.");
+    }
 
     /*
     =================================
@@ -106,6 +163,11 @@ impl Renderer {
 
     let camera_buffer = UniformBuffer::<CameraUniform>::new(&device, UniformBufferUsage::Vertex, Some("Vertex camera buffer"));
     let camera_fragment_buffer = UniformBuffer::<CameraFragmentUniform>::new(&device, UniformBufferUsage::Fragment, Some("Fragment camera buffer"));
+    let palette_buffer = UniformBuffer::<BlockPaletteUniform>::new(&device, UniformBufferUsage::Vertex, Some("Block palette buffer"));
+    palette_buffer.update(&queue, BlockPaletteUniform::new());
+    let chunk_origin_buffer = ArrayBuffer::new(&device, &queue, GenericBufferType::Vertex, &[], 1024);
+    let draw_indirect_buffer = ArrayBuffer::new(&device, &queue, GenericBufferType::Indirect, &[], 1024 * size_of::<DrawIndexedIndirect>());
+    let mesh_arena = MeshArena::new(&device);
 
     
     //Load world terrain shader module.
@@ -117,7 +179,7 @@ impl Renderer {
     let depth_texture = Texture::create_depth_texture(&device, &surface_cfg, "Depth texture and stuff");
 
     let terrain_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-      bind_group_layouts: &[&camera_buffer.get_bind_group_layout(), &camera_fragment_buffer.get_bind_group_layout()],
+      bind_group_layouts: &[camera_buffer.get_bind_group_layout(), camera_fragment_buffer.get_bind_group_layout(), palette_buffer.get_bind_group_layout()],
       label: Some("pipeline layout"),
       push_constant_ranges: &[]
     });
@@ -131,7 +193,7 @@ impl Renderer {
         bias: DepthBiasState::default(),
     }),
       vertex: VertexState {
-        buffers: &[ChunkVertex::desc()],
+        buffers: &[ChunkVertex::desc(), ChunkOrigin::desc()],
         entry_point: "vs_main",
         module: &terrain_shader_module
       },
@@ -222,6 +284,63 @@ impl Renderer {
 
     let sky_vertex_buffer = ArrayBuffer::new(&device, &queue, GenericBufferType::Vertex, &SKY_VERTICES, 0);
 
+    /*
+    =================================
+    OVERLAY SHADER STUFF
+    =================================
+    */
+
+    //Load overlay shader module.
+    let overlay_shader = include_str!("../shaders/overlay.wgsl");
+    let overlay_shader_module = device.create_shader_module(ShaderModuleDescriptor { 
+      label: Some("Overlay shader module"), 
+      source: ShaderSource::Wgsl(Cow::from(overlay_shader))
+    });
+
+    let overlay_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+      bind_group_layouts: &[camera_buffer.get_bind_group_layout()],
+      label: Some("Overlay pipeline layout"),
+      push_constant_ranges: &[]
+    });
+
+    let outline_pipeline = create_overlay_pipeline(&device, &overlay_shader_module, &overlay_pipeline_layout, surface_cfg.format, OverlayPipelineDesc {
+      label: "Block outline pipeline",
+      vertex_entry: "vs_world",
+      vertex_layout: OverlayVertex::desc(),
+      topology: PrimitiveTopology::LineList,
+      depth_compare: CompareFunction::LessEqual, //Hidden behind terrain.
+      blend: BlendState::REPLACE,
+    });
+
+    let ghost_pipeline = create_overlay_pipeline(&device, &overlay_shader_module, &overlay_pipeline_layout, surface_cfg.format, OverlayPipelineDesc {
+      label: "Paste preview pipeline",
+      vertex_entry: "vs_world",
+      vertex_layout: OverlayVertex::desc(),
+      topology: PrimitiveTopology::TriangleList,
+      depth_compare: CompareFunction::LessEqual,
+      blend: BlendState::ALPHA_BLENDING, //Translucent so the terrain can be seen through it.
+    });
+
+    let crosshair_pipeline = create_overlay_pipeline(&device, &overlay_shader_module, &overlay_pipeline_layout, surface_cfg.format, OverlayPipelineDesc {
+      label: "Crosshair pipeline",
+      vertex_entry: "vs_screen",
+      vertex_layout: SkyVertex::desc(),
+      topology: PrimitiveTopology::LineList,
+      depth_compare: CompareFunction::Always, //Always drawn on top.
+      blend: BlendState { //Invert the colour behind the crosshair so it can always be seen.
+        color: BlendComponent {
+          src_factor: BlendFactor::OneMinusDst,
+          dst_factor: BlendFactor::Zero,
+          operation: BlendOperation::Add,
+        },
+        alpha: BlendComponent::REPLACE,
+      },
+    });
+
+    let outline_vertex_buffer = ArrayBuffer::new(&device, &queue, GenericBufferType::Vertex, &[], OUTLINE_VERTEX_COUNT * 3);
+    let ghost_vertex_buffer = ArrayBuffer::new(&device, &queue, GenericBufferType::Vertex, &[], GHOST_VERTEX_COUNT);
+    let crosshair_vertex_buffer = ArrayBuffer::new(&device, &queue, GenericBufferType::Vertex, &crosshair_vertices(size), 0);
+
     Ok(Self {
       surface,
       surface_cfg,
@@ -231,10 +350,22 @@ impl Renderer {
       camera_buffer,
       camera_fragment_buffer,
       terrain_pipeline,
+      chunk_origin_buffer,
+      draw_indirect_buffer,
+      mesh_arena,
+      multi_draw,
+      palette_buffer,
       sky_pipeline,
       sky_vertex_buffer,
       sky_camera_buffer,
       sky_fragment_buffer,
+      outline_pipeline,
+      outline_vertex_buffer,
+      ghost_pipeline,
+      ghost_vertex_buffer,
+      crosshair_pipeline,
+      crosshair_vertex_buffer,
+      mesh_uploader: MeshUploader::new(),
       size,
       world: None,
       imgui,
@@ -243,8 +374,17 @@ impl Renderer {
     })
   }
 
-  pub fn get_device_queue(&self) -> (Arc<Device>, Arc<Queue>) {
-    (self.device.clone(), self.queue.clone())
+  pub fn get_mesh_memory_counter(&self) -> MeshMemoryCounter {
+    self.mesh_arena.get_usage_counter()
+  }
+
+  ///The most mesh memory that can be used before the GPU's buffer size limit is reached.
+  pub fn get_max_mesh_memory(&self) -> usize {
+    self.mesh_arena.max_memory()
+  }
+
+  pub fn get_mesh_upload_sender(&self) -> MeshUploadSender {
+    self.mesh_uploader.get_sender()
   }
 
   pub fn resize(&mut self, size: PhysicalSize<u32>) {
@@ -254,6 +394,7 @@ impl Renderer {
       self.surface_cfg.height = size.height;
       self.surface.configure(&self.device, &self.surface_cfg);
       self.depth_texture = Texture::create_depth_texture(&self.device, &self.surface_cfg, "another depth texture");
+      self.crosshair_vertex_buffer.update(&self.device, &self.queue, &crosshair_vertices(size));
     }
   }
 
@@ -275,17 +416,53 @@ impl Renderer {
         },
     };
 
-    let (view_mat, player_pos, chunk_list, light_data, pos_fpv) = {
+    self.mesh_arena.reclaim();
+    self.mesh_uploader.upload(&mut self.mesh_arena, &self.device, &self.queue);
+
+    let (view_mat, player_pos, chunk_list, lod_list, chunk_cache, pipeline, memory, light_data, pos_fpv, target_block, selection_corners, selected_block, history, paste_region, clipboard_size, paste_transform, load_area) = {
       let world_lock = world.lock().unwrap();
+      let history = world_lock.get_history();
       (
         world_lock.get_player_view(self.size.width as f32/self.size.height as f32), 
         world_lock.get_player_pos_c(),
-        world_lock.get_terrain().get_meshes(),
+        world_lock.get_terrain().get_render_chunks(),
+        world_lock.get_terrain().get_lod_meshes(),
+        world_lock.get_terrain().get_cache_info(),
+        world_lock.get_terrain().get_pipeline_stats(),
+        (world_lock.get_terrain().get_memory_usage(), world_lock.get_terrain().get_memory_budget(), world_lock.get_terrain().get_effective_load_area().radius),
         world_lock.get_daylight_data(),
         world_lock.get_player_pos(),
+        world_lock.get_targeted_block(),
+        world_lock.get_selection_corners(),
+        world_lock.get_selected_block(),
+        HistoryInfo {
+          undo_count: history.undo_count(),
+          redo_count: history.redo_count(),
+          memory_used: history.memory_used(),
+          memory_cap: history.memory_cap(),
+        },
+        world_lock.get_paste_region(),
+        world_lock.get_clipboard_size(),
+        world_lock.get_paste_transform(),
+        world_lock.get_terrain().get_load_area(),
       )
     };
 
+    //Outline the targeted block, the selected region and where the clipboard would be pasted.
+    let mut outline_vertices = Vec::with_capacity(OUTLINE_VERTEX_COUNT * 3);
+    if let Some(hit) = target_block.as_ref() {
+      outline_vertices.extend(box_outline_vertices(Region::from_corners(hit.block_pos, hit.block_pos), TARGET_OUTLINE_COLOUR));
+    }
+    if let [Some(a), b] | [b, Some(a)] = selection_corners { //Show a single block if only one corner has been selected.
+      outline_vertices.extend(box_outline_vertices(Region::from_corners(a, b.unwrap_or(a)), SELECTION_OUTLINE_COLOUR));
+    }
+    if let Some(region) = paste_region {
+      outline_vertices.extend(box_outline_vertices(region, PASTE_OUTLINE_COLOUR));
+    }
+    self.outline_vertex_buffer.update(&self.device, &self.queue, &outline_vertices);
+    let ghost_vertices = paste_region.map_or(Vec::new(), |region| box_face_vertices(region, PASTE_GHOST_COLOUR));
+    self.ghost_vertex_buffer.update(&self.device, &self.queue, &ghost_vertices);
+
     let camera_view = CameraUniform {
       view: view_mat.into(),
       position_abs: player_pos.block_int.into(),
@@ -355,10 +532,67 @@ impl Renderer {
       sky_render_pass.draw(0..SKY_VERTICES.len() as u32, 0..1);
     }
 
-    {
-      //Filter out empty chunks.
-      let chunk_datas = chunk_list.into_iter().filter_map(|(_, data)| if data.index_buffer.1 > 0 {Some(data)} else {None}).collect_vec();
+    //Filter out empty chunks, chunks outside the view and chunks hidden behind solid chunks (like caves).
+    //Each chunk's position is drawn as an instance so the vertices don't need to store it.
+    let frustum = Frustum::from_matrix(view_mat);
+    let camera_offset = player_pos.block_int.to_vec().map(|v| v as f32) + player_pos.block_dec.to_vec();
+    let in_frustum = |chunk_id: &[i32; 3]| {
+      let min = Vector3::from(chunk_id.map(|v| (v * CHUNK_SIZE_I32) as f32)) - camera_offset; //Relative to the camera like in terrain.wgsl.
+      frustum.intersects_aabb(min, min + Vector3::new(CHUNK_SIZE_I32 as f32, CHUNK_SIZE_I32 as f32, CHUNK_SIZE_I32 as f32))
+    };
+    let camera_chunk: [i32; 3] = ChunkedTerrain::pos_to_chunk_id(player_pos.block_int.to_vec()).into();
+    let connectivity: HashMap<[i32; 3], ChunkConnectivity> = chunk_list.iter().map(|(chunk_id, connectivity, _)| (*chunk_id, *connectivity)).collect();
+    let visible_chunks = find_visible_chunks(camera_chunk, |chunk_id| {
+      connectivity.get(&chunk_id).copied().filter(|_| chunk_id == camera_chunk || in_frustum(&chunk_id))
+    });
+
+    let non_empty_chunks = chunk_list.into_iter()
+      .filter_map(|(chunk_id, _, data)| data.filter(|data| !data.indices.is_empty()).map(|data| (chunk_id, data)))
+      .collect_vec();
+    let non_empty_count = non_empty_chunks.len();
+    let (chunk_datas, hidden_chunks): (Vec<_>, Vec<_>) = non_empty_chunks.into_iter()
+      .filter(|(chunk_id, _)| in_frustum(chunk_id))
+      .partition(|(chunk_id, _)| visible_chunks.contains(chunk_id));
+    let (chunks_drawn, chunks_occluded) = (chunk_datas.len(), hidden_chunks.len());
+    let chunks_culled = non_empty_count - chunks_drawn - chunks_occluded;
+
+    //LOD tiles are drawn with the same pipeline, with each cell stretched to the tile's scale.
+    let lod_datas = lod_list.into_iter()
+      .filter(|(origin, scale, data)| {
+        let min = Vector3::from(origin.map(|v| v as f32)) - camera_offset;
+        let size = (CHUNK_SIZE_I32 * scale) as f32;
+        !data.indices.is_empty() && frustum.intersects_aabb(min, min + Vector3::new(size, size * 2.0, size)) //Tiles can be up to 64 cells high.
+      })
+      .collect_vec();
+    let lod_tiles_drawn = lod_datas.len();
+
+    let chunk_origins = chunk_datas.iter().map(|(chunk_id, _)| ChunkOrigin {
+      position: chunk_id.map(|v| v * CHUNK_SIZE_I32),
+      scale: 1
+    }).chain(lod_datas.iter().map(|(origin, scale, _)| ChunkOrigin {
+      position: *origin,
+      scale: *scale as u32
+    })).collect_vec();
+    self.chunk_origin_buffer.update(&self.device, &self.queue, &chunk_origins);
+    let meshes = chunk_datas.iter().map(|(_, data)| data).chain(lod_datas.iter().map(|(_, _, data)| data)).collect_vec();
+    if self.multi_draw {
+      let draw_args = meshes.iter().enumerate().flat_map(|(i, data)| DrawIndexedIndirect {
+        vertex_count: data.indices.len() as u32,
+        instance_count: 1,
+        base_index: data.indices.start,
+        vertex_offset: data.base_vertex as i32,
+        base_instance: i as u32, //The instance picks the chunk origin.
+      }.as_bytes().to_vec()).collect_vec();
+      self.draw_indirect_buffer.update(&self.device, &self.queue, &draw_args);
+    }
 
+    let chunk_origin_buf = self.chunk_origin_buffer.get_buffer();
+    let draw_indirect_buf = self.draw_indirect_buffer.get_buffer();
+    let (arena_vertex_buf, arena_index_buf) = (self.mesh_arena.get_vertex_buffer(), self.mesh_arena.get_index_buffer());
+    let outline_buf = self.outline_vertex_buffer.get_buffer();
+    let ghost_buf = self.ghost_vertex_buffer.get_buffer();
+    let crosshair_buf = self.crosshair_vertex_buffer.get_buffer();
+    {
       // let ll = light_data.light_level.pow(2.2) as f64;
       let mut terrain_render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
         color_attachments: &[Some(RenderPassColorAttachment {
@@ -387,17 +621,42 @@ impl Renderer {
         occlusion_query_set: None,
         timestamp_writes: None
       });
-      terrain_render_pass.set_bind_group(0, &self.camera_buffer.get_bind_group(), &[]); //Set player and camera uniform./Chunk uniform group
-      terrain_render_pass.set_bind_group(1, &self.camera_fragment_buffer.get_bind_group(), &[]); //Set camera data to fragment shader too.
+      terrain_render_pass.set_bind_group(0, self.camera_buffer.get_bind_group(), &[]); //Set player and camera uniform./Chunk uniform group
+      terrain_render_pass.set_bind_group(1, self.camera_fragment_buffer.get_bind_group(), &[]); //Set camera data to fragment shader too.
+      terrain_render_pass.set_bind_group(2, self.palette_buffer.get_bind_group(), &[]);
 
       terrain_render_pass.set_pipeline(&self.terrain_pipeline);
+      if !meshes.is_empty() {
+        //Every mesh is in the arena so the buffers only need setting once.
+        terrain_render_pass.set_vertex_buffer(0, arena_vertex_buf.slice(..));
+        terrain_render_pass.set_vertex_buffer(1, chunk_origin_buf.slice(..(meshes.len() * size_of::<ChunkOrigin>()) as u64));
+        terrain_render_pass.set_index_buffer(arena_index_buf.slice(..), IndexFormat::Uint32);
+
+        if self.multi_draw {
+          terrain_render_pass.multi_draw_indexed_indirect(&draw_indirect_buf, 0, meshes.len() as u32);
+        } else {
+          for (i, data) in meshes.iter().enumerate() {
+            terrain_render_pass.draw_indexed(data.indices.clone(), data.base_vertex as i32, i as u32..i as u32 + 1); //The instance picks the chunk origin.
+          }
+        }
+      }
+
+      //Draw overlays after the terrain so they can use its depth.
+      if self.outline_vertex_buffer.len() > 0 {
+        terrain_render_pass.set_pipeline(&self.outline_pipeline);
+        terrain_render_pass.set_vertex_buffer(0, outline_buf.slice(..));
+        terrain_render_pass.draw(0..self.outline_vertex_buffer.len() as u32, 0..1);
+      }
 
-      for data in chunk_datas.iter(){
-        terrain_render_pass.set_vertex_buffer(0, data.vertex_buffer.0.slice(..data.vertex_buffer.1 * size_of::<ChunkVertex>() as u64));
-        terrain_render_pass.set_index_buffer(data.index_buffer.0.slice(..data.index_buffer.1 * size_of::<u32>() as u64), IndexFormat::Uint32);
-        terrain_render_pass.draw_indexed(0..data.index_buffer.1 as u32, 0, 0..1);
+      if self.ghost_vertex_buffer.len() > 0 {
+        terrain_render_pass.set_pipeline(&self.ghost_pipeline);
+        terrain_render_pass.set_vertex_buffer(0, ghost_buf.slice(..));
+        terrain_render_pass.draw(0..self.ghost_vertex_buffer.len() as u32, 0..1);
       }
-      
+
+      terrain_render_pass.set_pipeline(&self.crosshair_pipeline);
+      terrain_render_pass.set_vertex_buffer(0, crosshair_buf.slice(..));
+      terrain_render_pass.draw(0..self.crosshair_vertex_buffer.len() as u32, 0..1);
     }
     
     
@@ -414,10 +673,30 @@ impl Renderer {
     //Process imgui data.
     let data = ImguiData {
         fps: fps_avg,
-        player_pos: pos_fpv
+        player_pos: pos_fpv,
+        selection_corners,
+        selected_block,
+        history,
+        clipboard_size,
+        paste_transform,
+        load_area,
+        chunks_drawn,
+        chunks_culled,
+        chunks_occluded,
+        lod_tiles_drawn,
+        mesh_memory: self.mesh_arena.memory_used(),
+        chunk_cache,
+        pipeline,
+        memory,
     };
 
-    self.imgui.render(&data, &mut encoder, &self.device, &self.queue, &view, &depth_view)?;
+    let commands = self.imgui.render(&data, &mut encoder, &self.device, &self.queue, &view, &depth_view)?;
+    if !commands.is_empty() {
+      let mut world_lock = world.lock().unwrap();
+      for command in commands {
+        world_lock.run_command(command);
+      }
+    }
 
     let command_buffers = std::iter::once(encoder.finish());
     self.queue.submit(command_buffers);
@@ -472,7 +751,11 @@ impl RendererImgui {
     RendererImgui {
       ui: imgui_ctx,
       platform: imgui_platform,
-      renderer: imgui_renderer
+      renderer: imgui_renderer,
+      edit_menu: EditMenuState {
+        replace_block: 0
+      },
+      debug_menu: DebugMenuState::default()
     }
   }
 
@@ -480,25 +763,181 @@ impl RendererImgui {
     self.platform.handle_event(self.ui.io_mut(), window, event);
   }
 
-  fn prep_window(frame: &mut imgui::Ui, data: &ImguiData) {
+  ///Builds the UI. Returns any commands that need to be sent to the world.
+  fn prep_window(frame: &mut imgui::Ui, data: &ImguiData, edit_menu: &mut EditMenuState, debug_menu: &mut DebugMenuState) -> Vec<WorldCommand> {
+    let mut commands = Vec::new();
     let fps_string = data.fps.map_or(String::from("???"), |fps| format!("{:.1}", fps));
+    const MB: f32 = 1024.0 * 1024.0;
 
     frame.window("Debug Menu")
-      .size([300.0, 150.0], imgui::Condition::FirstUseEver)
+      .size([300.0, 340.0], imgui::Condition::FirstUseEver)
       .build(|| {
         frame.text_colored([1.0, 1.0, 0.7, 1.0], "Hold ALT to access cursor...");
         frame.text_wrapped(format!("FPS: {}", fps_string));
         frame.text_wrapped(format!("X: {:.4}", {data.player_pos.inner.x}));
         frame.text_wrapped(format!("Y: {:.4}", {data.player_pos.inner.y}));
         frame.text_wrapped(format!("Z: {:.4}", {data.player_pos.inner.z}));
+        frame.text_wrapped(format!("Chunks drawn: {} (culled: {}, occluded: {})", data.chunks_drawn, data.chunks_culled, data.chunks_occluded));
+        frame.text_wrapped(format!("LOD tiles drawn: {}", data.lod_tiles_drawn));
+        frame.text_wrapped(format!("Chunk cache: {} chunks ({:.2}MB)", data.chunk_cache.0, data.chunk_cache.1 as f32 / MB));
+        let (usage, budget, effective_radius) = data.memory;
+        let budget_string = |used: usize, budget: usize| if budget == 0 {
+          format!("{:.1}MB", used as f32 / MB)
+        } else {
+          format!("{:.1}MB of {:.1}MB", used as f32 / MB, budget as f32 / MB)
+        };
+        frame.text_wrapped(format!("Block memory: {}", budget_string(usage.blocks, budget.blocks)));
+        frame.text_wrapped(format!("Mesh memory: {}", budget_string(usage.meshes, budget.meshes)));
+        if effective_radius < data.load_area.radius {
+          frame.text_colored([1.0, 0.4, 0.4, 1.0], format!("Over memory budget! Render distance reduced to {}.", effective_radius));
+        }
+
+        frame.separator();
+        //The sliders only change the load area when they are let go, so chunks aren't loaded and unloaded for every value they are dragged past.
+        let mut load_area = debug_menu.pending_load_area.unwrap_or(data.load_area);
+        frame.slider("Render distance", 1, 32, &mut load_area.radius);
+        let mut finished = frame.is_item_deactivated_after_edit();
+        frame.slider("Vertical distance", 1, 16, &mut load_area.vertical_radius);
+        finished |= frame.is_item_deactivated_after_edit();
+        let mut is_sphere = load_area.shape == LoadShape::Sphere;
+        if frame.checkbox("Spherical load area", &mut is_sphere) {
+          load_area.shape = if is_sphere {LoadShape::Sphere} else {LoadShape::Cylinder};
+          finished = true;
+        }
+        if finished {
+          debug_menu.pending_load_area = None;
+          if load_area != data.load_area {
+            commands.push(WorldCommand::SetLoadArea(load_area));
+          }
+        } else {
+          debug_menu.pending_load_area = (load_area != data.load_area).then_some(load_area);
+        }
+      });
+
+    frame.window("Chunk Pipeline")
+      .size([360.0, 420.0], imgui::Condition::FirstUseEver)
+      .position([340.0, 20.0], imgui::Condition::FirstUseEver)
+      .build(|| {
+        let ms = |duration: Duration| duration.as_secs_f32() * 1000.0;
+        frame.columns(6, "Pipeline tasks", true);
+        for header in ["Task", "Queued", "Running", "Done", "Avg ms", "p95 ms"] {
+          frame.text(header);
+          frame.next_column();
+        }
+        frame.separator();
+        for (kind, stats) in TaskKind::ALL.iter().zip(data.pipeline.tasks.iter()) {
+          let cells = [format!("{:?}", kind), stats.queued.to_string(), stats.in_flight.to_string(), stats.finished.to_string(), format!("{:.2}", ms(stats.average)), format!("{:.2}", ms(stats.p95))];
+          for cell in cells {
+            frame.text(cell);
+            frame.next_column();
+          }
+        }
+        frame.columns(1, "Pipeline tasks", false);
+
+        frame.separator();
+        frame.text("Chunks per stage:");
+        for (stage, count) in ChunkStateStage::ALL.iter().zip(data.pipeline.chunks_per_stage.iter()) {
+          frame.text(format!("  {:?}: {}", stage, count));
+        }
+        frame.text_wrapped(format!("Mesh memory: {:.2}MB used of {:.2}MB", data.mesh_memory.0 as f32 / MB, data.mesh_memory.1 as f32 / MB));
+
+        frame.separator();
+        frame.text_wrapped(format!("Task durations (each bar is twice as long as the last, up to {}ms):", histogram_bucket_limit(HISTOGRAM_BUCKETS - 2)));
+        for (kind, stats) in TaskKind::ALL.iter().zip(data.pipeline.tasks.iter()) {
+          frame.plot_histogram(format!("{:?}", kind), &stats.histogram)
+            .graph_size([200.0, 40.0])
+            .build();
+        }
+      });
+
+    frame.window("World Edit")
+      .size([300.0, 380.0], imgui::Condition::FirstUseEver)
+      .position([20.0, 280.0], imgui::Condition::FirstUseEver)
+      .build(|| {
+        frame.text_colored([1.0, 1.0, 0.7, 1.0], "Press [ and ] to select corners...");
+        for (i, corner) in data.selection_corners.iter().enumerate() {
+          let corner_string = corner.map_or(String::from("None"), |c| format!("{}, {}, {}", c.x, c.y, c.z));
+          frame.text_wrapped(format!("Corner {}: {}", i + 1, corner_string));
+        }
+
+        fn block_name(block: &Block) -> Cow<'_, str> {
+          Cow::Owned(format!("{:?}", block))
+        }
+        let mut block_index = Block::ALL.iter().position(|b| *b == data.selected_block).unwrap_or(0);
+        if frame.combo("Block", &mut block_index, &Block::ALL, block_name) {
+          commands.push(WorldCommand::SelectBlock(Block::ALL[block_index]));
+        }
+        let block = Block::ALL[block_index];
+
+        let buttons = [
+          ("Fill", RegionOperation::Fill(block)),
+          ("Hollow", RegionOperation::Hollow(block)),
+          ("Walls", RegionOperation::Walls(block)),
+          ("Sphere", RegionOperation::Sphere(block)),
+        ];
+        for (i, (label, operation)) in buttons.into_iter().enumerate() {
+          if i > 0 {
+            frame.same_line();
+          }
+          if frame.button(label) {
+            commands.push(WorldCommand::RegionEdit(operation));
+          }
+        }
+
+        frame.separator();
+        frame.combo("Replace", &mut edit_menu.replace_block, &Block::ALL, block_name);
+        if frame.button("Replace with block") {
+          commands.push(WorldCommand::RegionEdit(RegionOperation::Replace(Block::ALL[edit_menu.replace_block], block)));
+        }
+
+        frame.separator();
+        if frame.button(format!("Undo ({})", data.history.undo_count)) {
+          commands.push(WorldCommand::Undo);
+        }
+        frame.same_line();
+        if frame.button(format!("Redo ({})", data.history.redo_count)) {
+          commands.push(WorldCommand::Redo);
+        }
+        const MB: usize = 1024 * 1024;
+        frame.text_wrapped(format!("History: {:.2}MB used (Z to undo, Y to redo)", data.history.memory_used as f32 / MB as f32));
+        let mut cap_mb = (data.history.memory_cap / MB) as u32;
+        if frame.slider("History cap (MB)", 1, 1024, &mut cap_mb) {
+          commands.push(WorldCommand::SetHistoryMemoryCap(cap_mb as usize * MB));
+        }
+
+        frame.separator();
+        let clipboard_string = data.clipboard_size.map_or(String::from("Empty"), |s| format!("{}x{}x{}", s.x, s.y, s.z));
+        frame.text_wrapped(format!("Clipboard: {} (C copy, X cut, V paste)", clipboard_string));
+        for (i, (label, command)) in [("Copy", WorldCommand::Copy), ("Cut", WorldCommand::Cut), ("Paste", WorldCommand::Paste)].into_iter().enumerate() {
+          if i > 0 {
+            frame.same_line();
+          }
+          if frame.button(label) {
+            commands.push(command);
+          }
+        }
+
+        let mut transform = data.paste_transform;
+        if frame.button(format!("Rotate ({} deg)", transform.rotation as u32 * 90)) {
+          transform.rotate();
+        }
+        frame.same_line();
+        frame.checkbox("Mirror X", &mut transform.mirror_x);
+        frame.same_line();
+        frame.checkbox("Mirror Z", &mut transform.mirror_z);
+        if transform != data.paste_transform {
+          commands.push(WorldCommand::SetPasteTransform(transform));
+        }
       });
+
+    commands
   }
 
 
-  fn render(&mut self, data: &ImguiData, encoder: &mut CommandEncoder, device: &Device, queue: &Queue, view: &TextureView, depth_view: &TextureView) -> Result<(), RenderError> {
+  fn render(&mut self, data: &ImguiData, encoder: &mut CommandEncoder, device: &Device, queue: &Queue, view: &TextureView, depth_view: &TextureView) -> Result<Vec<WorldCommand>, RenderError> {
     let frame = self.ui.frame();
     
-    Self::prep_window(frame, data);
+    let commands = Self::prep_window(frame, data, &mut self.edit_menu, &mut self.debug_menu);
     // let mut demo_open = true;
     //TODO make debug menu
     // if demo_open {
@@ -529,7 +968,7 @@ impl RendererImgui {
 
     self.renderer.render(self.ui.render(), queue, device, &mut rpass).map_err(|_| RenderError::ImguiError)?;
 
-    Ok(())
+    Ok(commands)
     
   }
 }
@@ -576,34 +1015,103 @@ impl Descriptable for ChunkVertex {
         array_stride: std::mem::size_of::<ChunkVertex>() as u64,
         step_mode: VertexStepMode::Vertex,
         attributes: &[
-          VertexAttribute {
-            format: VertexFormat::Sint32x3,
+          VertexAttribute { //Packed position, side and block.
+            format: VertexFormat::Uint32,
             offset: 0,
-            shader_location: 0, //Absolute position.
-          },
-          VertexAttribute { //Relative Position
-            format: VertexFormat::Float32x3,
-            offset: size_of::<[i32; 3]>() as u64,
-            shader_location: 1
-          },
-          VertexAttribute { //Colour
-            format: VertexFormat::Float32x4,
-            offset: size_of::<[i32; 3]>() as u64 + size_of::<[f32; 3]>() as u64,
-            shader_location: 2
-          },
-          VertexAttribute { //Vertex normal
-            format: VertexFormat::Float32x3,
-            offset: size_of::<[i32; 3]>() as u64 + size_of::<[f32; 3]>() as u64 + size_of::<[f32; 4]>() as u64,
-            shader_location: 3
+            shader_location: 0,
           }
         ],
       }
     }
 }
 
+///The position of a chunk's lowest corner in blocks. One per chunk (or LOD tile) drawn.
 #[derive(Clone, Copy, Pod, Zeroable)]
 #[repr(C)]
-struct SkyVertex { //Used to draw the sky.
+struct ChunkOrigin {
+  position: [i32; 3],
+  scale: u32 //Blocks per vertex position unit. Only LOD tiles use more than 1.
+}
+
+impl Descriptable for ChunkOrigin {
+  fn desc<'a>() -> VertexBufferLayout<'a> {
+    VertexBufferLayout {
+      array_stride: std::mem::size_of::<ChunkOrigin>() as u64,
+      step_mode: VertexStepMode::Instance,
+      attributes: &[
+        VertexAttribute {
+          format: VertexFormat::Sint32x3,
+          offset: 0,
+          shader_location: 1
+        },
+        VertexAttribute {
+          format: VertexFormat::Uint32,
+          offset: size_of::<[i32; 3]>() as u64,
+          shader_location: 2
+        }
+      ],
+    }
+  }
+}
+
+const PALETTE_SIZE: usize = 8; //Must match terrain.wgsl.
+
+///Block colours, indexed by the block in each vertex.
+#[derive(Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct BlockPaletteUniform {
+  pub colours: [[f32; 4]; PALETTE_SIZE]
+}
+
+impl BlockPaletteUniform {
+  fn new() -> Self {
+    let mut colours = [[1.0, 0.0, 1.0, 1.0]; PALETTE_SIZE];
+    for block in Block::ALL {
+      colours[block as usize] = block.get_colour();
+    }
+    Self {
+      colours
+    }
+  }
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct OverlayVertex { //Used for drawing things on top of the terrain.
+  pub absolute_position: [i32; 3],
+  pub relative_position: [f32; 3],
+  pub colour: [f32; 4]
+}
+
+impl Descriptable for OverlayVertex {
+  fn desc<'a>() -> VertexBufferLayout<'a> {
+    VertexBufferLayout {
+      array_stride: std::mem::size_of::<OverlayVertex>() as u64,
+      step_mode: VertexStepMode::Vertex,
+      attributes: &[
+        VertexAttribute { //Absolute position
+          format: VertexFormat::Sint32x3,
+          offset: 0,
+          shader_location: 0,
+        },
+        VertexAttribute { //Relative Position
+          format: VertexFormat::Float32x3,
+          offset: size_of::<[i32; 3]>() as u64,
+          shader_location: 1
+        },
+        VertexAttribute { //Colour
+          format: VertexFormat::Float32x4,
+          offset: size_of::<[i32; 3]>() as u64 + size_of::<[f32; 3]>() as u64,
+          shader_location: 2
+        },
+      ],
+    }
+  }
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct SkyVertex { //Used to draw the sky (and anything else in screen space).
   pub screen_position: [f32; 2]
 }
 
@@ -632,6 +1140,115 @@ const SKY_VERTICES: [SkyVertex; 6] = [
   SkyVertex {screen_position: [1.0, -1.0]}, //Bottom right
 ];
 
+const OUTLINE_VERTEX_COUNT: usize = 24; //12 edges.
+const TARGET_OUTLINE_COLOUR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const SELECTION_OUTLINE_COLOUR: [f32; 4] = [1.0, 0.8, 0.0, 1.0];
+const PASTE_OUTLINE_COLOUR: [f32; 4] = [0.3, 0.6, 1.0, 1.0];
+const PASTE_GHOST_COLOUR: [f32; 4] = [0.3, 0.6, 1.0, 0.25];
+const GHOST_VERTEX_COUNT: usize = 36; //6 faces of 2 triangles.
+
+///Gets a corner of a box around a region of blocks (same numbering as cubedirections.png).
+fn box_corner(region: Region, num: usize, colour: [f32; 4]) -> OverlayVertex {
+  const EXPAND: f32 = 0.002; //Make the box slightly bigger than the blocks to stop it z-fighting.
+  let size = region.size().map(|v| v as f32);
+
+  OverlayVertex {
+    absolute_position: region.lo.into(),
+    relative_position: [(num & 0b100, size.x), (num & 0b10, size.y), (num & 0b1, size.z)].map(|(v, s)| if v > 0 {s + EXPAND} else {-EXPAND}),
+    colour
+  }
+}
+
+///Creates a wireframe box (as a line list) around a region of blocks.
+fn box_outline_vertices(region: Region, colour: [f32; 4]) -> Vec<OverlayVertex> {
+  //An edge connects two corners that differ by one bit.
+  (0..8usize).flat_map(|num| [0b100, 0b10, 0b1].into_iter()
+    .filter(move |bit| num & bit == 0)
+    .flat_map(move |bit| [box_corner(region, num, colour), box_corner(region, num | bit, colour)])
+  ).collect_vec()
+}
+
+///Creates a solid box (as a triangle list) around a region of blocks.
+fn box_face_vertices(region: Region, colour: [f32; 4]) -> Vec<OverlayVertex> {
+  const AXES: [usize; 3] = [0b100, 0b10, 0b1];
+  let mut vertices = Vec::with_capacity(GHOST_VERTEX_COUNT);
+
+  for (i, axis) in AXES.into_iter().enumerate() {
+    let (a, b) = (AXES[(i + 1) % 3], AXES[(i + 2) % 3]);
+    for side in [0, axis] { //Low and high face on each axis.
+      let quad = [side, side | a, side | a | b, side | b].map(|num| box_corner(region, num, colour));
+      vertices.extend([0, 1, 2, 0, 2, 3].map(|j| quad[j]));
+    }
+  }
+  vertices
+}
+
+///Creates a crosshair in the middle of the screen (as a line list).
+fn crosshair_vertices(size: PhysicalSize<u32>) -> [SkyVertex; 4] {
+  const HALF_LENGTH: f32 = 10.0; //In pixels.
+  let (x, y) = (HALF_LENGTH * 2.0 / size.width as f32, HALF_LENGTH * 2.0 / size.height as f32); //Convert to screen space.
+
+  [
+    SkyVertex {screen_position: [-x, 0.0]}, //Horizontal line
+    SkyVertex {screen_position: [x, 0.0]},
+    SkyVertex {screen_position: [0.0, -y]}, //Vertical line
+    SkyVertex {screen_position: [0.0, y]},
+  ]
+}
+
+struct OverlayPipelineDesc<'a> {
+  label: &'a str,
+  vertex_entry: &'a str,
+  vertex_layout: VertexBufferLayout<'a>,
+  topology: PrimitiveTopology,
+  depth_compare: CompareFunction,
+  blend: BlendState
+}
+
+///Creates a pipeline for the overlay shader. Overlays are drawn in the terrain pass so they are depth tested but don't write to the depth buffer.
+fn create_overlay_pipeline(device: &Device, module: &ShaderModule, layout: &PipelineLayout, format: TextureFormat, desc: OverlayPipelineDesc) -> RenderPipeline {
+  device.create_render_pipeline(&RenderPipelineDescriptor {
+    depth_stencil: Some(DepthStencilState {
+      format: Texture::DEPTH_FORMAT,
+      depth_write_enabled: false,
+      depth_compare: desc.depth_compare,
+      stencil: StencilState::default(),
+      bias: DepthBiasState::default(),
+    }),
+    vertex: VertexState {
+      buffers: &[desc.vertex_layout],
+      entry_point: desc.vertex_entry,
+      module
+    },
+    fragment: Some(FragmentState {
+      entry_point: "fs_main",
+      module,
+      targets: &[Some(ColorTargetState {
+        blend: Some(desc.blend),
+        format,
+        write_mask: ColorWrites::ALL
+      })]
+    }),
+    label: Some(desc.label),
+    layout: Some(layout),
+    multisample: MultisampleState {
+      count: 1,
+      mask: !0,
+      alpha_to_coverage_enabled: false,
+    },
+    multiview: None,
+    primitive: PrimitiveState {
+      topology: desc.topology,
+      strip_index_format: None,
+      front_face: FrontFace::Ccw,
+      cull_mode: None,
+      unclipped_depth: false,
+      polygon_mode: PolygonMode::Fill,
+      conservative: false,
+    },
+  })
+}
+
 #[derive(Debug)]
 pub enum RendererCreateError {
   NoDeviceFound,
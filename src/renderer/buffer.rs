@@ -18,7 +18,8 @@ pub struct ArrayBuffer<T: Pod> {
 #[derive(Clone, Copy)]
 pub enum GenericBufferType {
   Vertex,
-  Index,
+  // Index,
+  Indirect,
   // Uniform
 }
 
@@ -30,7 +31,8 @@ impl<T: Pod> ArrayBuffer<T> {
 
     let usage = match typ {
         GenericBufferType::Vertex => BufferUsages::VERTEX,
-        GenericBufferType::Index => BufferUsages::INDEX,
+        // GenericBufferType::Index => BufferUsages::INDEX,
+        GenericBufferType::Indirect => BufferUsages::INDIRECT,
         // GenericBufferType::Uniform => BufferUsages::UNIFORM,
     };
 
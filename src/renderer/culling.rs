@@ -0,0 +1,75 @@
+use cgmath::{Matrix4, Vector3, Vector4, InnerSpace};
+
+///The planes of the camera's view. Anything outside one of them can't be seen.
+pub struct Frustum {
+  planes: [Vector4<f32>; 6] //(normal, distance). Points inside have a positive distance to every plane.
+}
+
+impl Frustum {
+  ///Gets the frustum from a view-projection matrix (like the one from Player::get_view_matrix). Clip space depth goes from 0 to 1 like in wgpu.
+  pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+    let row = |i: usize| Vector4::new(view_proj.x[i], view_proj.y[i], view_proj.z[i], view_proj.w[i]);
+    let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+    let planes = [
+      r3 + r0, //Left.
+      r3 - r0, //Right.
+      r3 + r1, //Bottom.
+      r3 - r1, //Top.
+      r2, //Near.
+      r3 - r2, //Far.
+    ].map(|plane| plane / plane.truncate().magnitude());
+
+    Self {
+      planes
+    }
+  }
+
+  ///Checks if a box might be visible. Boxes that are close to a corner of the frustum can be let through even when they're outside.
+  pub fn intersects_aabb(&self, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+    self.planes.iter().all(|plane| {
+      //The corner furthest along the plane's normal.
+      let corner = Vector3::new(
+        if plane.x >= 0.0 {max.x} else {min.x},
+        if plane.y >= 0.0 {max.y} else {min.y},
+        if plane.z >= 0.0 {max.z} else {min.z},
+      );
+      plane.truncate().dot(corner) + plane.w >= 0.0
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use cgmath::{Deg, Matrix4, Point3, Vector3};
+
+  use crate::util::projection;
+
+  use super::Frustum;
+
+  //Camera at the origin looking along +z, the same way the player's view is built.
+  fn test_frustum() -> Frustum {
+    let view = Matrix4::look_to_lh(Point3::new(0.0, 0.0, 0.0), Vector3::unit_z(), Vector3::unit_y());
+    Frustum::from_matrix(projection(Deg(90.0), 1.0, 0.1, 400.0) * view)
+  }
+
+  fn cube(x: f32, y: f32, z: f32) -> (Vector3<f32>, Vector3<f32>) {
+    let min = Vector3::new(x, y, z);
+    (min, min + Vector3::new(32.0, 32.0, 32.0))
+  }
+
+  #[test]
+  fn test_visible() {
+    let frustum = test_frustum();
+    for (min, max) in [cube(-16.0, -16.0, 10.0), cube(-16.0, -16.0, -16.0), cube(40.0, 0.0, 100.0)] { //In front, around the camera and off to the side.
+      assert!(frustum.intersects_aabb(min, max));
+    }
+  }
+
+  #[test]
+  fn test_culled() {
+    let frustum = test_frustum();
+    for (min, max) in [cube(-16.0, -16.0, -50.0), cube(100.0, 0.0, 10.0), cube(0.0, -200.0, 50.0), cube(0.0, 0.0, 500.0)] { //Behind, right, below and past the far plane.
+      assert!(!frustum.intersects_aabb(min, max));
+    }
+  }
+}
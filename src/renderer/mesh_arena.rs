@@ -0,0 +1,334 @@
+use std::{collections::BTreeMap, mem::size_of, ops::Range, sync::{Arc, atomic::{AtomicUsize, Ordering}, mpsc::{channel, Receiver, Sender}}};
+
+use bytemuck::cast_slice;
+use wgpu::{Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Device, Queue};
+
+use crate::world::mesher::{ChunkMeshData, ChunkVertex};
+
+const INITIAL_VERTICES: u32 = 1 << 22; //16MB. Enough for a render distance of 8 without growing.
+const INITIAL_INDICES: u32 = INITIAL_VERTICES / 2 * 3; //Every quad has 4 vertices and 6 indices.
+
+///How many bytes of the mesh arena are in use. Shared with the world so it can stay under its memory budget.
+pub type MeshMemoryCounter = Arc<AtomicUsize>;
+
+///Hands out ranges of a buffer (in elements). Freed ranges are merged with their neighbours so the space can be reused for bigger meshes.
+pub struct RangeAllocator {
+  size: u32,
+  free: BTreeMap<u32, u32> //Start -> end of every free range.
+}
+
+impl RangeAllocator {
+  pub fn new(size: u32) -> Self {
+    let mut free = BTreeMap::new();
+    if size > 0 {
+      free.insert(0, size);
+    }
+    Self {
+      size,
+      free
+    }
+  }
+
+  ///Takes the first free range that is big enough. Empty ranges don't use any space.
+  pub fn allocate(&mut self, len: u32) -> Option<Range<u32>> {
+    if len == 0 {
+      return Some(0..0);
+    }
+    let (&start, &end) = self.free.iter().find(|(start, end)| **end - **start >= len)?;
+    self.free.remove(&start);
+    if end - start > len {
+      self.free.insert(start + len, end);
+    }
+    Some(start..start + len)
+  }
+
+  pub fn free(&mut self, range: Range<u32>) {
+    if range.is_empty() {
+      return;
+    }
+    let (mut start, mut end) = (range.start, range.end);
+    if let Some((&prev_start, &prev_end)) = self.free.range(..start).next_back() {
+      if prev_end == start {
+        self.free.remove(&prev_start);
+        start = prev_start;
+      }
+    }
+    if let Some(next_end) = self.free.remove(&end) {
+      end = next_end;
+    }
+    self.free.insert(start, end);
+  }
+
+  ///Like allocate, but grows the space (doubling it, up to `max_size`) if there isn't a big enough gap.
+  ///Returns the range and the old size if it had to grow, or None if it can't fit even at `max_size`. Nothing changes if it doesn't fit.
+  pub fn allocate_or_grow(&mut self, len: u32, max_size: u32) -> Option<(Range<u32>, Option<u32>)> {
+    if let Some(range) = self.allocate(len) {
+      return Some((range, None));
+    }
+
+    //The free range at the end gets bigger when growing, so only the rest needs to be added.
+    let free_at_end = self.free.iter().next_back().filter(|(_, end)| **end == self.size).map_or(0, |(start, end)| end - start);
+    let needed = self.size.checked_add(len - free_at_end).filter(|needed| *needed <= max_size)?;
+    let mut new_size = self.size.max(1);
+    while new_size < needed {
+      new_size = new_size.saturating_mul(2);
+    }
+    let old_size = self.size;
+    self.grow(new_size.min(max_size));
+    Some((self.allocate(len)?, Some(old_size)))
+  }
+
+  ///Adds space to the end.
+  pub fn grow(&mut self, new_size: u32) {
+    let old_size = self.size;
+    self.size = new_size;
+    self.free(old_size..new_size);
+  }
+
+  pub fn size(&self) -> u32 {
+    self.size
+  }
+
+  pub fn free_space(&self) -> u32 {
+    self.free.iter().map(|(start, end)| end - start).sum()
+  }
+}
+
+///Where a mesh is stored in the arena. The space is given back to the arena when this is dropped.
+pub struct MeshAllocation {
+  vertices: Range<u32>,
+  indices: Range<u32>,
+  freed: Sender<(Range<u32>, Range<u32>)>
+}
+
+impl MeshAllocation {
+  ///Added to every index when drawing, because the indices in the mesh start at 0.
+  pub fn base_vertex(&self) -> u32 {
+    self.vertices.start
+  }
+
+  pub fn indices(&self) -> Range<u32> {
+    self.indices.clone()
+  }
+
+  ///How many bytes of the arena this takes up.
+  pub fn memory_used(&self) -> usize {
+    self.vertices.len() * size_of::<ChunkVertex>() + self.indices.len() * size_of::<u32>()
+  }
+}
+
+impl Drop for MeshAllocation {
+  fn drop(&mut self) {
+    let _ = self.freed.send((self.vertices.clone(), self.indices.clone())); //The arena might have been dropped first.
+  }
+}
+
+///One big vertex buffer and index buffer that every chunk mesh is packed into, so they can all be drawn without switching buffers.
+///Meshes can be dropped on any thread, so the freed space is only reused after reclaim is called.
+pub struct MeshArena {
+  vertex_buffer: Arc<Buffer>,
+  index_buffer: Arc<Buffer>,
+  vertices: RangeAllocator,
+  indices: RangeAllocator,
+  max_vertices: u32, //The buffers can't grow past the device's buffer size limit.
+  max_indices: u32,
+  freed: Receiver<(Range<u32>, Range<u32>)>,
+  free_sender: Sender<(Range<u32>, Range<u32>)>,
+  full: bool, //Set when an upload didn't fit, so the warning is only printed once.
+  usage: MeshMemoryCounter
+}
+
+impl MeshArena {
+  pub fn new(device: &Device) -> Self {
+    let (free_sender, freed) = channel();
+    let max_buffer_size = device.limits().max_buffer_size;
+    let max_len = |element_size: usize| (max_buffer_size / element_size as u64).min(u32::MAX as u64) as u32;
+    Self {
+      vertex_buffer: Arc::new(create_buffer::<ChunkVertex>(device, BufferUsages::VERTEX, INITIAL_VERTICES)),
+      index_buffer: Arc::new(create_buffer::<u32>(device, BufferUsages::INDEX, INITIAL_INDICES)),
+      vertices: RangeAllocator::new(INITIAL_VERTICES),
+      indices: RangeAllocator::new(INITIAL_INDICES),
+      max_vertices: max_len(size_of::<ChunkVertex>()),
+      max_indices: max_len(size_of::<u32>()),
+      freed,
+      free_sender,
+      full: false,
+      usage: MeshMemoryCounter::default()
+    }
+  }
+
+  ///Copies a mesh into the arena. The buffers are doubled in size if there isn't a big enough gap.
+  ///Returns None if the mesh doesn't fit even after growing the buffers as big as the device allows. The memory budget should shrink the load area before this happens.
+  pub fn upload(&mut self, device: &Device, queue: &Queue, data: &ChunkMeshData) -> Option<MeshAllocation> {
+    self.reclaim();
+    let vertices = self.vertices.allocate_or_grow(data.vertices.len() as u32, self.max_vertices);
+    let indices = self.indices.allocate_or_grow(data.indices.len() as u32, self.max_indices);
+    let (vertices, indices) = match (vertices, indices) {
+      (Some(vertices), Some(indices)) => (vertices, indices),
+      (vertices, indices) => {
+        //Give back whichever one did fit. Any growing that happened still needs copying into the bigger buffer.
+        if let Some((range, old_size)) = vertices {
+          self.vertices.free(range);
+          self.grow_vertex_buffer(device, queue, old_size);
+        }
+        if let Some((range, old_size)) = indices {
+          self.indices.free(range);
+          self.grow_index_buffer(device, queue, old_size);
+        }
+        if !self.full {
+          eprintln!("Mesh arena is full, so some meshes won't be drawn.");
+          self.full = true;
+        }
+        return None;
+      },
+    };
+    self.grow_vertex_buffer(device, queue, vertices.1);
+    self.grow_index_buffer(device, queue, indices.1);
+    let (vertices, indices) = (vertices.0, indices.0);
+    self.full = false;
+    self.update_usage();
+
+    if !data.vertices.is_empty() {
+      queue.write_buffer(&self.vertex_buffer, (vertices.start as usize * size_of::<ChunkVertex>()) as u64, cast_slice(&data.vertices));
+      queue.write_buffer(&self.index_buffer, (indices.start as usize * size_of::<u32>()) as u64, cast_slice(&data.indices));
+    }
+
+    Some(MeshAllocation {
+      vertices,
+      indices,
+      freed: self.free_sender.clone()
+    })
+  }
+
+  ///Makes the vertex buffer match the size of its allocator if it grew from `old_size`.
+  fn grow_vertex_buffer(&mut self, device: &Device, queue: &Queue, old_size: Option<u32>) {
+    if let Some(old_size) = old_size {
+      self.vertex_buffer = Arc::new(grow_buffer::<ChunkVertex>(device, queue, &self.vertex_buffer, BufferUsages::VERTEX, old_size, self.vertices.size()));
+    }
+  }
+
+  fn grow_index_buffer(&mut self, device: &Device, queue: &Queue, old_size: Option<u32>) {
+    if let Some(old_size) = old_size {
+      self.index_buffer = Arc::new(grow_buffer::<u32>(device, queue, &self.index_buffer, BufferUsages::INDEX, old_size, self.indices.size()));
+    }
+  }
+
+  ///Gets back the space of every mesh that has been dropped since the last call.
+  pub fn reclaim(&mut self) {
+    for (vertices, indices) in self.freed.try_iter() {
+      self.vertices.free(vertices);
+      self.indices.free(indices);
+    }
+    self.update_usage();
+  }
+
+  fn update_usage(&self) {
+    self.usage.store(self.memory_used().0 as usize, Ordering::Relaxed);
+  }
+
+  ///Gets a counter of the bytes used by meshes that stays up to date as meshes are uploaded and freed.
+  pub fn get_usage_counter(&self) -> MeshMemoryCounter {
+    self.usage.clone()
+  }
+
+  ///The most bytes of meshes that fit before one of the buffers reaches the device's size limit (assuming every mesh is made of quads).
+  pub fn max_memory(&self) -> usize {
+    let quads = (self.max_vertices / 4).min(self.max_indices / 6) as usize;
+    quads * (4 * size_of::<ChunkVertex>() + 6 * size_of::<u32>())
+  }
+
+  ///How many bytes of the buffers are used by meshes, and how big the buffers are.
+  pub fn memory_used(&self) -> (u64, u64) {
+    let bytes = |alloc: &RangeAllocator, element_size: usize| (alloc.size() as u64 * element_size as u64, alloc.free_space() as u64 * element_size as u64);
+    let (vertex_size, vertex_free) = bytes(&self.vertices, size_of::<ChunkVertex>());
+    let (index_size, index_free) = bytes(&self.indices, size_of::<u32>());
+    (vertex_size + index_size - vertex_free - index_free, vertex_size + index_size)
+  }
+
+  pub fn get_vertex_buffer(&self) -> Arc<Buffer> {
+    self.vertex_buffer.clone()
+  }
+
+  pub fn get_index_buffer(&self) -> Arc<Buffer> {
+    self.index_buffer.clone()
+  }
+}
+
+fn create_buffer<T>(device: &Device, usage: BufferUsages, len: u32) -> Buffer {
+  device.create_buffer(&BufferDescriptor {
+    label: Some("Mesh arena buffer"),
+    size: (len as usize * size_of::<T>()) as u64,
+    usage: usage | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+    mapped_at_creation: false,
+  })
+}
+
+///Makes a bigger buffer with the same contents. The copy is submitted straight away so writes after this go into the new buffer.
+fn grow_buffer<T>(device: &Device, queue: &Queue, old: &Buffer, usage: BufferUsages, old_len: u32, new_len: u32) -> Buffer {
+  let new = create_buffer::<T>(device, usage, new_len);
+  let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: Some("Mesh arena grow encoder") });
+  encoder.copy_buffer_to_buffer(old, 0, &new, 0, (old_len as usize * size_of::<T>()) as u64);
+  queue.submit(Some(encoder.finish()));
+  new
+}
+
+#[cfg(test)]
+mod tests {
+  use super::RangeAllocator;
+
+  #[test]
+  fn test_allocate_and_merge() {
+    let mut alloc = RangeAllocator::new(100);
+    let a = alloc.allocate(30).unwrap();
+    let b = alloc.allocate(30).unwrap();
+    let c = alloc.allocate(30).unwrap();
+    assert_eq!((a.clone(), b.clone(), c.clone()), (0..30, 30..60, 60..90));
+    assert!(alloc.allocate(20).is_none());
+
+    alloc.free(a);
+    alloc.free(c);
+    assert!(alloc.allocate(50).is_none()); //There are 70 free, but split into 30 and 40.
+    alloc.free(b);
+    assert_eq!(alloc.free_space(), 100);
+    assert_eq!(alloc.allocate(100), Some(0..100));
+  }
+
+  #[test]
+  fn test_first_fit_reuses_gaps() {
+    let mut alloc = RangeAllocator::new(100);
+    let a = alloc.allocate(10).unwrap();
+    let _b = alloc.allocate(10).unwrap();
+    alloc.free(a);
+    assert_eq!(alloc.allocate(5), Some(0..5));
+    assert_eq!(alloc.allocate(10), Some(20..30)); //Doesn't fit in the 5 left at the start.
+    assert_eq!(alloc.allocate(0), Some(0..0));
+  }
+
+  #[test]
+  fn test_allocate_or_grow() {
+    let mut alloc = RangeAllocator::new(10);
+    let _a = alloc.allocate(8).unwrap();
+    assert_eq!(alloc.allocate_or_grow(2, 40), Some((8..10, None)));
+    assert_eq!(alloc.allocate_or_grow(25, 40), Some((10..35, Some(10)))); //Doubled twice.
+    assert_eq!(alloc.size(), 40);
+  }
+
+  #[test]
+  fn test_allocate_past_max_size() {
+    let mut alloc = RangeAllocator::new(10);
+    let _a = alloc.allocate(6).unwrap();
+    assert_eq!(alloc.allocate_or_grow(20, 16), None); //Needs 26 but can only grow to 16.
+    assert_eq!(alloc.size(), 10); //Left alone when it doesn't fit.
+    assert_eq!(alloc.allocate_or_grow(10, 16), Some((6..16, Some(10)))); //Grows to the max instead of doubling past it.
+  }
+
+  #[test]
+  fn test_grow() {
+    let mut alloc = RangeAllocator::new(10);
+    let _a = alloc.allocate(8).unwrap();
+    assert!(alloc.allocate(4).is_none());
+    alloc.grow(20);
+    assert_eq!(alloc.allocate(4), Some(8..12)); //Uses the end of the old space too.
+    assert_eq!(alloc.free_space(), 8);
+  }
+}
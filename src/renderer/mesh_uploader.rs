@@ -0,0 +1,54 @@
+use std::sync::{Arc, mpsc::{channel, Receiver, Sender}};
+
+use wgpu::{Device, Queue};
+
+use super::mesh_arena::MeshArena;
+
+const MESH_UPLOADS_PER_FRAME: usize = 32; //More than this and frames start stuttering when lots of chunks load at once.
+
+///Something with a mesh built off the render thread that is waiting to be uploaded (like a chunk or an LOD tile).
+pub trait MeshUpload: Send + Sync {
+  ///Meshes of things that were unloaded before they got uploaded are skipped.
+  fn is_cancelled(&self) -> bool;
+  fn upload_mesh(&self, arena: &mut MeshArena, device: &Device, queue: &Queue);
+}
+
+pub type MeshUploadSender = Sender<Arc<dyn MeshUpload>>;
+
+///Uploads meshes built by the workers. Only a few are uploaded each frame and the rest wait for the next one.
+pub struct MeshUploader {
+  sender: MeshUploadSender,
+  pending: Receiver<Arc<dyn MeshUpload>>,
+}
+
+impl MeshUploader {
+  pub fn new() -> Self {
+    let (sender, pending) = channel();
+    Self {
+      sender,
+      pending
+    }
+  }
+
+  ///Workers send chunks with new meshes through this.
+  pub fn get_sender(&self) -> MeshUploadSender {
+    self.sender.clone()
+  }
+
+  ///Uploads up to MESH_UPLOADS_PER_FRAME meshes into the arena. Returns how many were uploaded.
+  pub fn upload(&self, arena: &mut MeshArena, device: &Device, queue: &Queue) -> usize {
+    let mut uploaded = 0;
+    while uploaded < MESH_UPLOADS_PER_FRAME {
+      let mesh = match self.pending.try_recv() {
+        Ok(mesh) => mesh,
+        Err(_) => break,
+      };
+      if mesh.is_cancelled() { //Unloaded before it got uploaded.
+        continue;
+      }
+      mesh.upload_mesh(arena, device, queue);
+      uploaded += 1;
+    }
+    uploaded
+  }
+}
@@ -1,22 +1,35 @@
 use std::{time::{Instant, Duration}, sync::{mpsc::Sender, Arc}, f32::consts::PI};
 
 use cgmath::{Deg, Matrix, Matrix3, Matrix4, Rad, Vector3};
-use winit::event::VirtualKeyCode;
+use winit::event::{MouseButton, VirtualKeyCode};
 
-use crate::util::FPVector;
+use crate::{util::FPVector, renderer::mesh_arena::MeshMemoryCounter};
 
-use self::{player::{Player, PlayerPosC}, controls::{Controller, Control}, chunkedterrain::ChunkedTerrain, chunk_worker_pool::ChunkTask, chunk::Chunk};
+use self::{player::{Player, PlayerPosC}, controls::{Controller, Control}, chunkedterrain::{ChunkedTerrain, TerrainSettings}, chunk_worker_pool::ChunkTaskQueue, chunk::Chunk, raycast::RaycastHit, block::Block, edit::{Region, RegionOperation, EditHistory, EditOrigin, EditRecord}, clipboard::{Clipboard, PasteTransform, MAX_CLIPBOARD_BLOCKS}, load_area::LoadArea};
 
-mod block;
+pub mod block;
 mod player;
 mod controls;
+pub mod raycast;
+pub mod edit;
+pub mod clipboard;
+pub mod load_area;
 pub mod chunkedterrain;
 pub mod chunk;
+pub mod chunk_state;
+pub mod chunk_cache;
+pub mod mesher;
+pub mod visibility;
+pub mod lod;
 pub mod chunk_worker_pool;
+pub mod pipeline_stats;
+pub mod memory_budget;
 
 
 const MOUSE_SENS: Rad<f32> = Rad(0.002); //Rads per dot.
 const NOCLIP_SPEED: f32 = 120.0; //blocks/sec
+const REACH_DISTANCE: f32 = 8.0; //How far away the player can target blocks from.
+pub const DEFAULT_HISTORY_MEMORY_CAP: usize = 64 * 1024 * 1024; //64MB of undo history.
 
 
 pub struct World {
@@ -24,17 +37,36 @@ pub struct World {
   player: Player,
   last_tick: Instant,
   controller: Controller,
-  uptime: Duration
+  uptime: Duration,
+  selected_block: Block, //The block that will be placed.
+  selection: [Option<Vector3<i32>>; 2], //Corners of the selected region.
+  history: EditHistory,
+  clipboard: Option<Clipboard>,
+  paste_transform: PasteTransform
+}
+
+///Actions that can be sent to the world from outside the tick thread (like from the debug UI).
+pub enum WorldCommand {
+  SelectBlock(Block),
+  RegionEdit(RegionOperation),
+  Undo,
+  Redo,
+  SetHistoryMemoryCap(usize), //In bytes.
+  Copy,
+  Cut,
+  Paste,
+  SetPasteTransform(PasteTransform),
+  SetLoadArea(LoadArea)
 }
 
 
 impl World {
-  pub fn new(worker_pool_sender: Sender<ChunkTask>, chunk_gc: Sender<Arc<Chunk>>) -> Self {
+  pub fn new(task_queue: Arc<ChunkTaskQueue>, chunk_gc: Sender<Arc<Chunk>>, terrain_settings: TerrainSettings, mesh_memory: MeshMemoryCounter) -> Self {
     let player_pos: FPVector = [1i32, 50, 1].into();
     
     let player = Player::new(player_pos.into());
     
-    let terrain = ChunkedTerrain::new(player_pos, 8, worker_pool_sender, chunk_gc);
+    let terrain = ChunkedTerrain::new(player_pos, terrain_settings, mesh_memory, task_queue, chunk_gc);
     let last_tick = Instant::now();
     let mut controller = Controller::new();
 
@@ -51,7 +83,23 @@ impl World {
       (VirtualKeyCode::Right, Control::Right),
       (VirtualKeyCode::RShift, Control::Up),
       (VirtualKeyCode::RControl, Control::Down),
-      (VirtualKeyCode::LAlt, Control::UnlockMouse)
+      (VirtualKeyCode::LAlt, Control::UnlockMouse),
+      (VirtualKeyCode::LBracket, Control::SelectCorner1),
+      (VirtualKeyCode::RBracket, Control::SelectCorner2),
+      (VirtualKeyCode::Z, Control::Undo),
+      (VirtualKeyCode::Y, Control::Redo),
+      (VirtualKeyCode::C, Control::Copy),
+      (VirtualKeyCode::X, Control::Cut),
+      (VirtualKeyCode::V, Control::Paste),
+      (VirtualKeyCode::R, Control::RotatePaste),
+      (VirtualKeyCode::M, Control::MirrorPasteX),
+      (VirtualKeyCode::N, Control::MirrorPasteZ)
+    ]);
+
+    controller.set_mouse_bindings(&[
+      (MouseButton::Left, Control::BreakBlock),
+      (MouseButton::Right, Control::PlaceBlock),
+      (MouseButton::Middle, Control::PickBlock)
     ]);
 
     let uptime = Duration::new(0, 0);
@@ -61,7 +109,12 @@ impl World {
       player,
       last_tick,
       controller,
-      uptime
+      uptime,
+      selected_block: Block::Stone,
+      selection: [None; 2],
+      history: EditHistory::new(DEFAULT_HISTORY_MEMORY_CAP),
+      clipboard: None,
+      paste_transform: PasteTransform::default()
     }
   }
 
@@ -74,6 +127,11 @@ impl World {
     self.player.get_rotation_matrix().z.into() //i guessed this
   }
 
+  ///Gets the block that the player is looking at (if it is within reach).
+  pub fn get_targeted_block(&self) -> Option<RaycastHit> {
+    self.terrain.raycast(self.player.get_position(), self.get_look_direction().into(), REACH_DISTANCE)
+  }
+
   pub fn get_player_pos(&self) -> FPVector {
     return self.player.get_position();
   }
@@ -107,8 +165,53 @@ impl World {
     self.player.tick_position(&accel, &delta_secs, &self.terrain);
 
     
+    if self.controller.take_action(Control::BreakBlock) {
+      self.break_block();
+    }
+    if self.controller.take_action(Control::PlaceBlock) {
+      self.place_block();
+    }
+    if self.controller.take_action(Control::PickBlock) {
+      self.pick_block();
+    }
+    if self.controller.take_action(Control::SelectCorner1) {
+      self.select_corner(0);
+    }
+    if self.controller.take_action(Control::SelectCorner2) {
+      self.select_corner(1);
+    }
+    if self.controller.take_action(Control::Undo) {
+      self.undo();
+    }
+    if self.controller.take_action(Control::Redo) {
+      self.redo();
+    }
+    if self.controller.take_action(Control::Copy) {
+      self.copy();
+    }
+    if self.controller.take_action(Control::Cut) {
+      self.cut();
+    }
+    if self.controller.take_action(Control::Paste) {
+      self.paste();
+    }
+    if self.controller.take_action(Control::RotatePaste) {
+      self.paste_transform.rotate();
+    }
+    if self.controller.take_action(Control::MirrorPasteX) {
+      self.paste_transform.mirror_x = !self.paste_transform.mirror_x;
+    }
+    if self.controller.take_action(Control::MirrorPasteZ) {
+      self.paste_transform.mirror_z = !self.paste_transform.mirror_z;
+    }
+    
+    self.terrain.tick_memory_budget();
     self.terrain.update_player_position(&self.player.get_position());
-    self.terrain.tick_progress();
+    self.terrain.tick_edits();
+    for (origin, record) in self.terrain.take_finished_edits() {
+      self.history.push(origin, record);
+    }
+    self.terrain.tick_progress(self.get_look_direction().into());
 
     // //For testing purposes only.
     // let p_pos = self.get_player_pos();
@@ -120,6 +223,154 @@ impl World {
     self.controller.set_key(key, state);
   }
 
+  pub fn mouse_button_update(&mut self, button: MouseButton, state: bool) {
+    self.controller.set_mouse_button(button, state);
+  }
+
+  fn break_block(&mut self) {
+    if let Some(hit) = self.get_targeted_block() {
+      if let Some(old_block) = self.terrain.set_block_at(hit.block_pos, Block::Air) {
+        self.history.push(EditOrigin::Edit, EditRecord::single_block(hit.block_pos, old_block));
+      }
+    }
+  }
+
+  fn place_block(&mut self) {
+    if let Some(hit) = self.get_targeted_block() {
+      let place_pos = hit.block_pos + hit.side.get_offset();
+      if self.player.is_inside_block(place_pos) { //Don't let the player place blocks inside themselves.
+        return;
+      }
+      if let Some(old_block) = self.terrain.set_block_at(place_pos, self.selected_block) {
+        self.history.push(EditOrigin::Edit, EditRecord::single_block(place_pos, old_block));
+      }
+    }
+  }
+
+  ///Puts back the blocks replaced by the last edit. The blocks that get replaced by undoing are kept so it can be redone.
+  fn undo(&mut self) {
+    if let Some(record) = self.history.take_undo() {
+      self.terrain.queue_block_edit(record, EditOrigin::Undo);
+    }
+  }
+
+  fn redo(&mut self) {
+    if let Some(record) = self.history.take_redo() {
+      self.terrain.queue_block_edit(record, EditOrigin::Redo);
+    }
+  }
+
+  ///Copies the selected region into the clipboard. Returns false if nothing was copied.
+  fn copy(&mut self) -> bool {
+    let region = match self.get_selection() {
+      Some(region) => region,
+      None => return false,
+    };
+    if region.block_count() > MAX_CLIPBOARD_BLOCKS {
+      println!("Can't copy more than {} blocks.", MAX_CLIPBOARD_BLOCKS);
+      return false;
+    }
+
+    match Clipboard::copy(&self.terrain, region) {
+      Some(clipboard) => {
+        self.clipboard = Some(clipboard);
+        self.paste_transform = PasteTransform::default();
+        true
+      },
+      None => {
+        println!("Can't copy a region that isn't fully loaded.");
+        false
+      },
+    }
+  }
+
+  ///Copies the selected region and then fills it with air.
+  fn cut(&mut self) {
+    if self.copy() {
+      self.run_command(WorldCommand::RegionEdit(RegionOperation::Fill(Block::Air)));
+    }
+  }
+
+  fn paste(&mut self) {
+    if let (Some(clipboard), Some(origin)) = (self.clipboard.as_ref(), self.get_paste_origin()) {
+      let record = clipboard.paste_record(origin, self.paste_transform);
+      self.terrain.queue_block_edit(record, EditOrigin::Edit);
+    }
+  }
+
+  ///Pastes go on the side of the targeted block that the player is looking at.
+  fn get_paste_origin(&self) -> Option<Vector3<i32>> {
+    self.get_targeted_block().map(|hit| hit.block_pos + hit.side.get_offset())
+  }
+
+  ///Gets the region that would be pasted into (for showing a preview).
+  pub fn get_paste_region(&self) -> Option<Region> {
+    let clipboard = self.clipboard.as_ref()?;
+    Some(clipboard.paste_region(self.get_paste_origin()?, self.paste_transform))
+  }
+
+  pub fn get_clipboard_size(&self) -> Option<Vector3<i32>> {
+    self.clipboard.as_ref().map(Clipboard::get_size)
+  }
+
+  pub fn get_paste_transform(&self) -> PasteTransform {
+    self.paste_transform
+  }
+
+  fn pick_block(&mut self) {
+    if let Some(block) = self.get_targeted_block().and_then(|hit| self.terrain.get_block_at(hit.block_pos)) {
+      self.selected_block = block;
+    }
+  }
+
+  ///Sets a corner of the selection to the targeted block.
+  fn select_corner(&mut self, corner: usize) {
+    if let Some(hit) = self.get_targeted_block() {
+      self.selection[corner] = Some(hit.block_pos);
+    }
+  }
+
+  pub fn get_selection_corners(&self) -> [Option<Vector3<i32>>; 2] {
+    self.selection
+  }
+
+  ///Gets the selected region if both corners have been selected.
+  pub fn get_selection(&self) -> Option<Region> {
+    match self.selection {
+      [Some(a), Some(b)] => Some(Region::from_corners(a, b)),
+      _ => None
+    }
+  }
+
+  pub fn get_selected_block(&self) -> Block {
+    self.selected_block
+  }
+
+  pub fn get_history(&self) -> &EditHistory {
+    &self.history
+  }
+
+  pub fn run_command(&mut self, command: WorldCommand) {
+    match command {
+      WorldCommand::SelectBlock(block) => self.selected_block = block,
+      WorldCommand::RegionEdit(operation) => {
+        if let Some(region) = self.get_selection() {
+          self.terrain.queue_region_edit(region, operation);
+        }
+      },
+      WorldCommand::Undo => self.undo(),
+      WorldCommand::Redo => self.redo(),
+      WorldCommand::SetHistoryMemoryCap(cap) => self.history.set_memory_cap(cap),
+      WorldCommand::Copy => {
+        self.copy();
+      },
+      WorldCommand::Cut => self.cut(),
+      WorldCommand::Paste => self.paste(),
+      WorldCommand::SetPasteTransform(transform) => self.paste_transform = transform,
+      WorldCommand::SetLoadArea(load_area) => self.terrain.set_load_area(load_area),
+    }
+  }
+
   pub fn get_terrain(&self) -> &ChunkedTerrain {
     &self.terrain
   }
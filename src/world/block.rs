@@ -1,3 +1,5 @@
+use cgmath::Vector3;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Block {
@@ -11,6 +13,16 @@ pub enum Block {
 }
 
 impl Block {
+  pub const ALL: [Block; 7] = [
+    Block::Stone,
+    Block::Grass,
+    Block::Bedrock,
+    Block::PinkStuff,
+    Block::YellowStuff,
+    Block::Cloud,
+    Block::Air
+  ];
+
   pub fn is_translucent(&self) -> bool {
     match self {
       Block::Air => true,
@@ -39,7 +51,7 @@ impl Block {
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BlockSide {
   Right = 0,
   Left = 1,
@@ -71,6 +83,22 @@ impl BlockSide {
         BlockSide::Front => [0.0, 0.0, -1.0], //Neg Z.
     }
   }
+
+  ///Gets the offset to the block on this side.
+  pub fn get_offset(&self) -> Vector3<i32> {
+    match self {
+        BlockSide::Right => [1, 0, 0],
+        BlockSide::Left => [-1, 0, 0],
+        BlockSide::Above => [0, 1, 0],
+        BlockSide::Below => [0, -1, 0],
+        BlockSide::Back => [0, 0, 1],
+        BlockSide::Front => [0, 0, -1],
+    }.into()
+  }
+
+  pub fn opposite(&self) -> BlockSide {
+    BlockSide::try_from(*self as u8 ^ 1).unwrap() //Opposite sides are next to each other.
+  }
 }
 
 impl TryFrom<u8> for BlockSide {
@@ -1,15 +1,13 @@
-use core::panic;
-use std::{sync::{Mutex, Arc, RwLock, TryLockError, MutexGuard, RwLockReadGuard}, ops::{Range, Deref}};
+use std::{sync::{Mutex, Arc, RwLock, MutexGuard, PoisonError, RwLockReadGuard, atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering}}, ops::{Range, Deref}, mem::size_of};
 
-use bytemuck_derive::{Zeroable, Pod};
 use cgmath::Vector3;
 use itertools::iproduct;
 use noise::{Perlin, NoiseFn};
 use wgpu::{Device, Queue};
 
-use crate::{renderer::buffer::{ArrayBuffer, GenericBufferType}, world::chunkedterrain::CHUNK_SIZE_I32};
+use crate::{renderer::{mesh_arena::{MeshAllocation, MeshArena}, mesh_uploader::MeshUpload}, world::chunkedterrain::CHUNK_SIZE_I32};
 
-use super::{block::{Block, BlockSideVisibility, BlockSide}, chunkedterrain::{SurfaceHeightmap, CHUNK_LENGTH, CHUNK_SIZE, CHUNK_RANGE}};
+use super::{block::{Block, BlockSideVisibility, BlockSide}, chunkedterrain::{SurfaceHeightmap, CHUNK_LENGTH, CHUNK_SIZE, CHUNK_RANGE}, chunk_state::{ChunkState, ChunkStateStage, ChunkStateProgress, ChunkTransition}, mesher::{ChunkMeshData, MeshAlgorithm}, visibility::ChunkConnectivity, chunk_cache::CachedChunk};
 
 pub const ADJACENT_OFFSETS: [[i32; 3]; 6] = [
   [1, 0, 0],
@@ -28,47 +26,31 @@ pub struct Chunk {
   chunk_id: [i32; 3],
   blocks: RwLock<Option<Vec<Block>>>,
   block_vis: RwLock<Option<Vec<BlockSideVisibility>>>,
-  mesh: Mutex<Option<ChunkMesh>>,
-  state: Mutex<ChunkState>
+  mesh: MeshSlot,
+  state: Mutex<ChunkState>,
+  modified: AtomicBool, //True if the blocks have been changed since generating, so they need saving when the chunk is unloaded.
+  cancelled: AtomicBool, //Set when the chunk is unloaded so workers skip any tasks that are still queued for it.
+  missing_sides: AtomicU8, //Sides (as BlockSide bit flags) that had no neighbouring blocks the last time the visibility was generated.
+  connectivity: AtomicU64 //ChunkConnectivity bits. Everything is connected until the visibility is generated.
 }
 
 
-struct ChunkState {
-  stage: ChunkStateStage,
-  progress: ChunkStateProgress
-}
-
 ///This is a temporary struct that keeps the RwLock for the chunk vis data unlocked for the lifetime of this struct.
 pub(crate) struct ChunkDataView<'a> {
   //Make sure that data lives as long as the struct
   data: Option<RwLockReadGuard<'a, Option<Vec<Block>>>>
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, PartialOrd, Ord)]
-pub enum ChunkStateStage {
-  ChunkGen,
-  ChunkVisGen,
-  MeshGen,
-  Ready
+///Where an uploaded chunk mesh is in the mesh arena.
+pub struct ChunkMeshRange {
+  pub base_vertex: u32,
+  pub indices: Range<u32>,
 }
 
-
-enum ChunkStateProgress {
-  Waiting,
-  TaskAssigned, //Activated before it is sent to another thread.
-  Processing, //When the processing is actually happening.
-  SwitchingTo(ChunkStateStage), //Can be used to interrupt the state.
-}
-
-
-struct ChunkMesh {
-  vertex_buffer: ArrayBuffer<ChunkVertex>,
-  index_buffer: ArrayBuffer<u32>,
-}
-
-pub struct ChunkMeshData {
-  pub vertex_buffer: (Arc<wgpu::Buffer>, u64),
-  pub index_buffer: (Arc<wgpu::Buffer>, u64),
+///Keeps a mesh built on the CPU until it gets uploaded, and then where it was uploaded to.
+pub struct MeshSlot {
+  mesh: Mutex<Option<MeshAllocation>>,
+  pending: Mutex<Option<ChunkMeshData>> //Built but not uploaded yet.
 }
 
 impl Chunk {
@@ -79,61 +61,80 @@ impl Chunk {
       chunk_id,
       blocks: RwLock::new(None),
       block_vis: RwLock::new(None),
-      mesh: Mutex::new(None),
-      state: Mutex::new(ChunkState {
-        stage: ChunkStateStage::ChunkGen,
-        progress: ChunkStateProgress::Waiting,
-      })
+      mesh: MeshSlot::new(),
+      state: Mutex::new(ChunkState::new(ChunkStateStage::ChunkGen)),
+      modified: AtomicBool::new(false),
+      cancelled: AtomicBool::new(false),
+      missing_sides: AtomicU8::new(0),
+      connectivity: AtomicU64::new(ChunkConnectivity::ALL.to_bits())
+    }
+  }
+
+  /// Create a chunk from one that was in the cache. The cached mesh is drawn until the new one is ready.
+  pub fn from_cached(chunk_id: [i32; 3], cached: CachedChunk) -> Self {
+    Self {
+      modified: AtomicBool::new(false),
+      mesh: MeshSlot::from_allocation(cached.mesh),
+      ..Self::from_blocks(chunk_id, cached.blocks)
+    }
+  }
+
+  /// Create a chunk from blocks that have already been generated (like from a chunk that was modified and then unloaded).
+  pub fn from_blocks(chunk_id: [i32; 3], blocks: Vec<Block>) -> Self {
+    Self {
+      chunk_id,
+      blocks: RwLock::new(Some(blocks)),
+      block_vis: RwLock::new(None),
+      mesh: MeshSlot::new(),
+      state: Mutex::new(ChunkState::new(ChunkStateStage::ChunkVisGen)),
+      modified: AtomicBool::new(true),
+      cancelled: AtomicBool::new(false),
+      missing_sides: AtomicU8::new(0),
+      connectivity: AtomicU64::new(ChunkConnectivity::ALL.to_bits())
     }
   }
 
-  ///Check if processing can start. Panics if something bad happens.
+  ///Check if processing can start. Returns false if the task should be skipped.
   fn start_process_check(&self, expected_stage: ChunkStateStage) -> bool {
-    let mut state = self.unlock_state();
-    if state.stage != expected_stage {
-      panic!("Function called at wrong stage!!!");
+    if self.is_cancelled() { //Nobody will see this chunk so don't bother.
+      return false;
     }
-    match state.progress {
-      ChunkStateProgress::Waiting => panic!("Chunk was not task assigned!"),
-      ChunkStateProgress::TaskAssigned => {
-        state.progress = ChunkStateProgress::Processing; 
-        true
-      },
-      ChunkStateProgress::Processing => { //Skip because the chunk is already processing.
-        false
-      },
-      ChunkStateProgress::SwitchingTo(t) => {
-        state.stage = t;
-        state.progress = ChunkStateProgress::Waiting;
+    let mut state = self.lock_state();
+    match state.apply(ChunkTransition::Start(expected_stage)) {
+      Ok(started) => started,
+      Err(err) => {
+        eprintln!("Chunk {:?}: {}", self.chunk_id, err);
+        if state.progress() == ChunkStateProgress::TaskAssigned { //The task is for the wrong stage, so let the right one get sent.
+          *state = ChunkState::new(state.stage());
+        }
         false
       },
     }
   }
 
-  fn end_process_check<T>(&self, current_stage: ChunkStateStage, next_stage: ChunkStateStage, success: T)
+  ///Moves the chunk to the next stage. Returns false if the chunk was switched to a different stage while processing.
+  fn end_process_check<T>(&self, current_stage: ChunkStateStage, success: T) -> bool
     where T: FnOnce() 
   { //Cursed brackets
-    let mut state = self.unlock_state();
-    if state.stage != current_stage {
-      panic!("Chunk stage changed mid processing: Expected: {:?}. Got: {:?}", current_stage, state.stage);
-    }
-    match state.progress {
-      ChunkStateProgress::Processing => {
-        success(); //Call success function.
-        state.stage = next_stage; //Go to next stage;
-        state.progress = ChunkStateProgress::Waiting;
+    let mut state = self.lock_state();
+    match state.apply(ChunkTransition::Finish(current_stage)) {
+      Ok(finished) => {
+        if finished {
+          success(); //Call success function.
+        }
+        finished
       },
-      ChunkStateProgress::SwitchingTo(new_state) => {
-        state.stage = new_state;
-        state.progress = ChunkStateProgress::Waiting;
+      Err(err) => {
+        eprintln!("Chunk {:?}: {}", self.chunk_id, err);
+        false
       },
-      _ => panic!("Chunk progress changed to invalid while processing.")
     }
   }
 
-  pub fn gen(&self, gen: &Perlin, surface_heightmap: &SurfaceHeightmap) {
+  ///Generates the blocks in the chunk. Returns true if the blocks were generated.
+  pub fn gen(&self, gen: &Perlin, surface_heightmap: &SurfaceHeightmap) -> bool {
     if !self.start_process_check(ChunkStateStage::ChunkGen) { //Skip if the chunk is not ready to generate.
-      return;
+      return false;
     }
 
     let chunk_pos = self.chunk_id.map(|chk| {
@@ -188,9 +189,9 @@ impl Chunk {
     }
 
     *self.blocks.write().unwrap() = Some(blocks); //Should move inside success function but oh well.
-    self.end_process_check(ChunkStateStage::ChunkGen, ChunkStateStage::ChunkVisGen, || {
+    self.end_process_check(ChunkStateStage::ChunkGen, || {
       
-    });
+    })
   }
 
   //TODO optimise get_block_at into a separate struct for multiple accesses (means RwLock doesn't need many reads).
@@ -203,6 +204,45 @@ impl Chunk {
     })
   }
 
+  /// Sets the block at the chunk-relative location and returns the block that was replaced. Returns None if the blocks have not yet been generated.
+  /// This does not update the visibility of the chunk, so mark_for_revis needs to be called afterwards.
+  pub fn set_block_at(&self, x: i32, y: i32, z: i32, block: Block) -> Option<Block> {
+    let mut block_lock = self.blocks.write().unwrap();
+    match (block_lock.as_mut(), Self::rel_pos_to_index(x, y, z)) {
+      (Some(blocks), Some(index)) => {
+        if blocks[index] != block {
+          self.modified.store(true, Ordering::Relaxed);
+        }
+        Some(std::mem::replace(&mut blocks[index], block))
+      },
+      _ => None
+    }
+  }
+
+  /// Reads the blocks of the chunk while holding the lock, so many blocks can be read at once. Returns None if the blocks have not yet been generated.
+  pub fn read_blocks<F, R>(&self, read: F) -> Option<R>
+    where F: FnOnce(&[Block]) -> R
+  {
+    self.blocks.read().unwrap().as_deref().map(read)
+  }
+
+  /// Edits the blocks of the chunk while holding the lock, so many blocks can be changed at once.
+  /// `edit` returns true if it changed anything, so chunks are only marked as modified if they were.
+  /// Returns false if the blocks have not yet been generated. Like set_block_at, this does not update the visibility of the chunk.
+  pub fn edit_blocks<F>(&self, edit: F) -> bool
+    where F: FnOnce(&mut Vec<Block>) -> bool
+  {
+    match self.blocks.write().unwrap().as_mut() {
+      Some(blocks) => {
+        if edit(blocks) {
+          self.modified.store(true, Ordering::Relaxed);
+        }
+        true
+      },
+      None => false
+    }
+  }
+
   pub fn get_vis_at(&self, x: i32, y: i32, z: i32) -> Option<BlockSideVisibility> {
     self.block_vis.read().unwrap().as_ref().and_then(|bv| {
       let index = Self::rel_pos_to_index(x, y, z)?;
@@ -210,57 +250,42 @@ impl Chunk {
     })
   }
 
-  /// Gets the surrounding blocks, or none if they are out of bounds.
-  pub fn get_surrounding_blocks_of(&self, x: i32, y: i32, z: i32) -> [Option<Block>; 6] {
-    let block_array_lock = self.blocks.read().unwrap();
-    let blocks = block_array_lock.as_ref();
-    match blocks {
-      Some(blocks) => {
-        ADJACENT_OFFSETS.map(|[ox, oy, oz]| { //Map offsets.
-          let pos = Vector3::from([ox + x, oy + y, oz + z]);
+  /// Gets the surrounding blocks from the chunk's (already locked) blocks, or none if they are out of bounds.
+  /// Takes the blocks rather than locking them again, because a queued edit can block a second read lock while the first is held.
+  fn get_surrounding_blocks_of(blocks: &[Block], x: i32, y: i32, z: i32) -> [Option<Block>; 6] {
+    ADJACENT_OFFSETS.map(|[ox, oy, oz]| { //Map offsets.
+      let pos = Vector3::from([ox + x, oy + y, oz + z]);
 
-          if Self::check_chunk_range(pos) {
-            Some(*blocks.get(pos.x as usize * CHUNK_SIZE * CHUNK_SIZE + pos.y as usize * CHUNK_SIZE + pos.z as usize).unwrap())
-          } else {
-            None
-          }
-        })
-      },
-      None => {
-        [None; 6]
-      },
-    }
+      if Self::check_chunk_range(pos) {
+        Some(*blocks.get(pos.x as usize * CHUNK_SIZE * CHUNK_SIZE + pos.y as usize * CHUNK_SIZE + pos.z as usize).unwrap())
+      } else {
+        None
+      }
+    })
   }
 
   pub fn assign_if_waiting(&self) -> bool {
-    let mut state = self.unlock_state();
-    match &state.progress {
-      ChunkStateProgress::Waiting => {
-        state.progress = ChunkStateProgress::TaskAssigned;
-        true
-      },
-      _ => false
-    }
+    self.lock_state().apply(ChunkTransition::Assign).unwrap_or(false)
   }
 
   ///Regen chunk visibility.
   pub fn mark_for_revis(&self) {
-    let mut state_lock = self.unlock_state();
-    if state_lock.stage >= ChunkStateStage::ChunkVisGen {
-      match state_lock.progress {
-        ChunkStateProgress::Waiting => {
-          *state_lock = ChunkState {
-            stage: ChunkStateStage::ChunkVisGen,
-            progress: ChunkStateProgress::Waiting,
-          }
-        },
-        ChunkStateProgress::TaskAssigned | ChunkStateProgress::Processing=> {
-          state_lock.progress = ChunkStateProgress::SwitchingTo(ChunkStateStage::ChunkVisGen);
-        },
-        ChunkStateProgress::SwitchingTo(old_switch) => {
-          state_lock.progress = ChunkStateProgress::SwitchingTo(std::cmp::min(old_switch, ChunkStateStage::ChunkVisGen));
-        },
-      }
+    if let Err(err) = self.lock_state().apply(ChunkTransition::Revis) {
+      eprintln!("Chunk {:?}: {}", self.chunk_id, err);
+    }
+  }
+
+  ///Called when the neighbouring chunk on a side has generated its blocks. Marks the chunk for revis if its faces on that side
+  ///were hidden because the neighbour was missing (or if the visibility is being generated right now and might have missed it).
+  pub fn neighbour_generated(&self, side: BlockSide) {
+    let was_missing = self.missing_sides.load(Ordering::Relaxed) & (1 << side as u8) != 0;
+    let generating_vis = {
+      let state = self.lock_state();
+      state.stage() == ChunkStateStage::ChunkVisGen && state.is_busy()
+    };
+
+    if was_missing || generating_vis {
+      self.mark_for_revis();
     }
   }
 
@@ -273,6 +298,7 @@ impl Chunk {
     let block_read_lock = self.blocks.read().unwrap();
     let blocks = block_read_lock.as_ref().unwrap();
     let mut surface_visibility = Vec::<BlockSideVisibility>::with_capacity(blocks.len());
+    let mut missing_sides = 0u8;
     for ((x, y, z), block_ref) in Self::block_iterator().zip(blocks.iter()) {
       let block = *block_ref;
       if let Block::Air = block {
@@ -280,7 +306,7 @@ impl Chunk {
         continue;
       }
 
-      let surroundings = self.get_surrounding_blocks_of(x as i32, y as i32, z as i32);
+      let surroundings = Self::get_surrounding_blocks_of(blocks, x as i32, y as i32, z as i32);
       let mut vis = BlockSideVisibility::new(false);
 
       for (index, block) in surroundings.into_iter().enumerate() { //Iterate each surrounding block.
@@ -290,11 +316,11 @@ impl Chunk {
             None => { //Adjacent block is outisde chunk.
               // Chunk relative position.
               let rel_pos = match side {
-                BlockSide::Right => [1, y, z],
+                BlockSide::Right => [0, y, z],
                 BlockSide::Left => [CHUNK_SIZE - 1, y, z],
-                BlockSide::Above => [x, 1, z],
+                BlockSide::Above => [x, 0, z],
                 BlockSide::Below => [x, CHUNK_SIZE - 1, z],
-                BlockSide::Back => [x, y, 1],
+                BlockSide::Back => [x, y, 0],
                 BlockSide::Front => [x, y, CHUNK_SIZE - 1],
               };
               
@@ -304,7 +330,10 @@ impl Chunk {
 
               let translucent = match block {
                 Some(block) => block.is_translucent(),
-                None => false,
+                None => { //The neighbour hasn't generated yet, so this side needs redoing once it has.
+                  missing_sides |= 1 << index;
+                  false
+                },
               };
 
               vis.set_visible(side, translucent);
@@ -314,86 +343,69 @@ impl Chunk {
       surface_visibility.push(vis);
     }
     *self.block_vis.write().unwrap() = Some(surface_visibility);
-    self.end_process_check(ChunkStateStage::ChunkVisGen, ChunkStateStage::MeshGen, || {
+    self.missing_sides.store(missing_sides, Ordering::Relaxed);
+    self.connectivity.store(ChunkConnectivity::compute(blocks).to_bits(), Ordering::Relaxed);
+    self.end_process_check(ChunkStateStage::ChunkVisGen, || {
       
     });
   }
 
-  /// Update the vertex buffer. gen_block_vis must be called at least once before this is called. This should only be called if the vertex state is outdated.
-  pub fn update_vertices(&self, device: &Device, queue: &Queue) { //Generate a vertex buffer for the chunk.
+  /// Builds the mesh on the CPU. gen_block_vis must be called at least once before this is called. The mesh is kept until upload_mesh is called.
+  /// Returns true if a new mesh was built.
+  pub fn gen_mesh(&self, algorithm: MeshAlgorithm) -> bool {
     if !self.start_process_check(ChunkStateStage::MeshGen) {
-      return;
+      return false;
     }
-    let block_vis_lock = self.block_vis.read().unwrap();
-    let block_vis = block_vis_lock.as_ref().expect("Please call gen_block_vis before generating vertices.");
-    let mut vertices = Vec::new();
-    let mut indices = Vec::new();
-    let chunk_pos = self.chunk_id.map(|val| val * CHUNK_SIZE_I32);
-    
-    const WINDING_ORDER: [u32; 6] = [0, 1, 2, 2, 3, 0];
-
-    for ((x, y, z), (block, block_visibility)) in Self::block_iterator().zip(self.blocks.read().unwrap().as_ref().unwrap().iter().zip(block_vis)) {
-      if block_visibility.is_invisible() {continue}; //Skip invisible blocks.
-      let colour = block.get_colour();
+    let mesh = {
+      let block_vis_lock = self.block_vis.read().unwrap();
+      let block_vis = block_vis_lock.as_ref().expect("Please call gen_block_vis before generating vertices.");
+      algorithm.build(self.blocks.read().unwrap().as_ref().unwrap(), block_vis)
+    };
+    if self.is_cancelled() { //Unloaded while meshing so don't keep anything.
+      return false;
+    }
+    self.end_process_check(ChunkStateStage::MeshGen, || {
+      self.mesh.set_pending(mesh);
+    })
+  }
 
-      for side_i in 0..6 { //Corresponds to BlockSide values.
-        let side = BlockSide::try_from(side_i).unwrap();
+  //Returns the vertex and index buffer unless they are being updated.
+  pub fn get_mesh_fast(&self) -> Option<ChunkMeshRange> {
+    self.mesh.get_fast()
+  }
 
-        if !block_visibility.get_visible(side) {continue}; //Skip this side if it is not visible.
+  ///How many bytes the blocks and the uploaded mesh use. Either is None if the chunk doesn't have it yet.
+  pub fn memory_used(&self) -> (Option<usize>, Option<usize>) {
+    let blocks = self.blocks.read().unwrap().as_ref().map(|blocks| blocks.len() * size_of::<Block>());
+    (blocks, self.mesh.memory_used())
+  }
 
-        let normal = side.get_face_normal(); //get the face normal.
-        let starting_index = vertices.len() as u32;
-        
-        for winding_index in WINDING_ORDER {
-          let index = starting_index + winding_index;
-          indices.push(index);
-        }
+  ///Takes the uploaded mesh out of the chunk (so it can be cached when the chunk is unloaded).
+  pub fn take_mesh(&self) -> Option<MeshAllocation> {
+    self.mesh.take_uploaded()
+  }
 
-        side.get_face_offset_vectors().iter().for_each(|vec| {
+  ///Which faces of the chunk can be seen through it (for cave culling).
+  pub fn get_connectivity(&self) -> ChunkConnectivity {
+    ChunkConnectivity::from_bits(self.connectivity.load(Ordering::Relaxed))
+  }
 
-          let v_pos = [vec[0] + x as f32, vec[1] + y as f32, vec[2] + z as f32];
-          vertices.push(ChunkVertex {
-            absolute_position: chunk_pos.clone(),
-            relative_position: v_pos,
-            colour,
-            normal
-          });
-        });
-      }
-    }
-    self.update_vertex_buffer(device, queue, vertices, indices);
-    self.end_process_check(ChunkStateStage::MeshGen, ChunkStateStage::Ready, || {
-      //update vertex buffer here instead???
-    });
+  ///Stops any more work being done on the chunk. Called when the chunk is unloaded.
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed);
+  }
 
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed)
   }
 
-  fn update_vertex_buffer(&self, device: &Device, queue: &Queue, vertices: Vec<ChunkVertex>, indices: Vec<u32>) {
-    let mut mesh_lock = self.mesh.lock().unwrap();
-    match mesh_lock.as_mut() {
-      Some(mesh) => {
-        mesh.vertex_buffer.update(device, queue, &vertices);
-        mesh.index_buffer.update(device, queue, &indices);
-      },
-      None => {
-        *mesh_lock = Some(
-          ChunkMesh {
-              vertex_buffer: ArrayBuffer::new(device, queue, GenericBufferType::Vertex, &vertices, 400),
-              index_buffer: ArrayBuffer::new(device, queue, GenericBufferType::Index, &indices, 600),
-          }
-        );
-      },
-    }
+  pub fn is_modified(&self) -> bool {
+    self.modified.load(Ordering::Relaxed)
   }
 
-  //Returns the vertex and index buffer unless they are being updated.
-  pub fn get_mesh_fast(&self) -> Option<ChunkMeshData> {
-    self.mesh.try_lock().ok()?.as_ref().map(|mesh| {
-      ChunkMeshData {
-        vertex_buffer: (mesh.vertex_buffer.get_buffer(), mesh.vertex_buffer.len() as u64),
-        index_buffer: (mesh.index_buffer.get_buffer(), mesh.index_buffer.len() as u64),
-      }
-    }) 
+  /// Copies the blocks of the chunk (if they have been generated).
+  pub fn clone_blocks(&self) -> Option<Vec<Block>> {
+    self.blocks.read().unwrap().clone()
   }
 
   pub fn get_id(&self) -> [i32; 3] {
@@ -401,31 +413,16 @@ impl Chunk {
   }
 
   pub fn get_pending_stage(&self) -> Option<ChunkStateStage> {
-    let state_lock = self.unlock_state();
-
-    match &state_lock.progress {
-      ChunkStateProgress::Waiting => {
-        Some(state_lock.stage)
-      },
-      _ => None
-    }
+    self.lock_state().pending_stage()
   }
 
   pub fn get_stage(&self) -> ChunkStateStage {
-    self.unlock_state().stage
+    self.lock_state().stage()
   }
 
-  //Spinloop chunk unlock.
-  fn unlock_state(&self) -> MutexGuard<ChunkState> {
-    loop {
-      match self.state.try_lock() {
-        Ok(lock) => {
-          return lock;
-        },
-        Err(TryLockError::Poisoned(_)) => panic!("Lock poisoned."),
-        Err(TryLockError::WouldBlock) => {}
-    }
-    }
+  ///The state is always left valid (transitions don't panic part way through), so a poisoned lock is fine to use.
+  fn lock_state(&self) -> MutexGuard<'_, ChunkState> {
+    self.state.lock().unwrap_or_else(PoisonError::into_inner)
   }
 
   ///Returns a reference for the block side visibility (if available). This is useful for accessing lots of chunk data at once without unlocking the rwlock for each block.
@@ -457,13 +454,71 @@ impl Chunk {
   }
   
   ///Check if a local coordinate is within range.
-  fn check_chunk_range(pos: Vector3<i32>) -> bool {
+  pub(super) fn check_chunk_range(pos: Vector3<i32>) -> bool {
     CHUNK_RANGE_I32.contains(&pos.x) && CHUNK_RANGE_I32.contains(&pos.y) && CHUNK_RANGE_I32.contains(&pos.z)
   }
 
   
 }
 
+impl MeshUpload for Chunk {
+  fn is_cancelled(&self) -> bool {
+    Chunk::is_cancelled(self)
+  }
+
+  fn upload_mesh(&self, arena: &mut MeshArena, device: &Device, queue: &Queue) {
+    self.mesh.upload(arena, device, queue);
+  }
+}
+
+impl MeshSlot {
+  pub fn new() -> Self {
+    Self::from_allocation(None)
+  }
+
+  ///Starts with a mesh that has already been uploaded.
+  pub fn from_allocation(mesh: Option<MeshAllocation>) -> Self {
+    Self {
+      mesh: Mutex::new(mesh),
+      pending: Mutex::new(None)
+    }
+  }
+
+  ///Replaces any mesh that is waiting to be uploaded.
+  pub fn set_pending(&self, data: ChunkMeshData) {
+    *self.pending.lock().unwrap() = Some(data);
+  }
+
+  pub fn take_pending(&self) -> Option<ChunkMeshData> {
+    self.pending.lock().unwrap().take()
+  }
+
+  pub fn memory_used(&self) -> Option<usize> {
+    self.mesh.lock().unwrap().as_ref().map(MeshAllocation::memory_used)
+  }
+
+  pub fn take_uploaded(&self) -> Option<MeshAllocation> {
+    self.mesh.lock().unwrap().take()
+  }
+
+  ///Copies the pending mesh into the arena. The space used by the old mesh is freed. Does nothing if there isn't a new mesh (like if it was already uploaded).
+  pub fn upload(&self, arena: &mut MeshArena, device: &Device, queue: &Queue) {
+    if let Some(data) = self.take_pending() {
+      *self.mesh.lock().unwrap() = arena.upload(device, queue, &data); //Left without a mesh if the arena is full.
+    }
+  }
+
+  //Returns where the mesh is unless it is being updated.
+  pub fn get_fast(&self) -> Option<ChunkMeshRange> {
+    self.mesh.try_lock().ok()?.as_ref().map(|mesh| {
+      ChunkMeshRange {
+        base_vertex: mesh.base_vertex(),
+        indices: mesh.indices(),
+      }
+    }) 
+  }
+}
+
 impl ChunkDataView<'_> {
   pub fn is_solid_at(&self, pos: Vector3<i32>) -> bool {
     let index = Chunk::rel_pos_to_index(pos.x, pos.y, pos.z).expect("Coordinate outside of local chunk range!");
@@ -504,11 +559,63 @@ impl ChunkDataView<'_> {
 //   }
 // }
 
-#[derive(Clone, Copy, Pod, Zeroable)]
-#[repr(C)]
-pub struct ChunkVertex {
-  absolute_position: [i32; 3],
-  relative_position: [f32; 3],
-  colour: [f32; 4],
-  normal: [f32; 3]
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+  use crate::world::{block::{Block, BlockSide}, chunkedterrain::CHUNK_LENGTH, chunk_cache::CachedChunk};
+
+  use super::{Chunk, ChunkStateStage, MeshAlgorithm};
+
+  //Generates the visibility of a chunk filled with one block, with no neighbours loaded.
+  fn chunk_with_vis(block: Block) -> Chunk {
+    let chunk = Chunk::from_blocks([0, 0, 0], vec![block; CHUNK_LENGTH]);
+    assert!(chunk.assign_if_waiting());
+    chunk.gen_block_vis(Default::default());
+    assert_eq!(chunk.get_stage(), ChunkStateStage::MeshGen);
+    chunk
+  }
+
+  #[test]
+  fn test_unchanged_edit_not_modified() {
+    let chunk = Chunk::from_cached([0, 0, 0], CachedChunk { blocks: vec![Block::Air; CHUNK_LENGTH], mesh: None });
+    assert!(!chunk.is_modified());
+    assert!(chunk.edit_blocks(|_| false));
+    assert_eq!(chunk.set_block_at(1, 2, 3, Block::Air), Some(Block::Air));
+    assert!(!chunk.is_modified());
+
+    assert!(chunk.edit_blocks(|blocks| {
+      blocks[0] = Block::Stone;
+      true
+    }));
+    assert!(chunk.is_modified());
+  }
+
+  #[test]
+  fn test_neighbour_generated_revis() {
+    let chunk = chunk_with_vis(Block::Stone);
+    chunk.neighbour_generated(BlockSide::Right);
+    assert_eq!(chunk.get_stage(), ChunkStateStage::ChunkVisGen);
+  }
+
+  #[test]
+  fn test_gen_mesh_headless() {
+    let mut blocks = vec![Block::Air; CHUNK_LENGTH];
+    blocks[Chunk::rel_pos_to_index(16, 16, 16).unwrap()] = Block::Stone;
+    let chunk = Chunk::from_blocks([0, 0, 0], blocks);
+    assert!(chunk.assign_if_waiting());
+    chunk.gen_block_vis(Default::default());
+    assert!(chunk.assign_if_waiting());
+    assert!(chunk.gen_mesh(MeshAlgorithm::Simple));
+    assert_eq!(chunk.get_stage(), ChunkStateStage::Ready);
+
+    let mesh = chunk.mesh.take_pending().unwrap();
+    assert_eq!(mesh.vertices.len(), 24); //All 6 faces of the stone block.
+    assert_eq!(mesh.indices.len(), 36);
+  }
+
+  #[test]
+  fn test_neighbour_generated_nothing_hidden() {
+    let chunk = chunk_with_vis(Block::Air); //Air has no faces, so nothing was hidden.
+    chunk.neighbour_generated(BlockSide::Right);
+    assert_eq!(chunk.get_stage(), ChunkStateStage::MeshGen);
+  }
+}
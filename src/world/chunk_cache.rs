@@ -0,0 +1,137 @@
+use std::{collections::{BTreeMap, HashMap}, mem::size_of};
+
+use crate::renderer::mesh_arena::MeshAllocation;
+
+use super::block::Block;
+
+///The blocks of a chunk that was unloaded, and its mesh if it had one (so it can be drawn again straight away).
+pub struct CachedChunk {
+  pub blocks: Vec<Block>,
+  pub mesh: Option<MeshAllocation>
+}
+
+impl CachedChunk {
+  ///Roughly how much memory this takes up, including the mesh on the GPU.
+  pub fn memory_used(&self) -> usize {
+    self.block_memory_used() + self.mesh.as_ref().map_or(0, MeshAllocation::memory_used)
+  }
+
+  pub fn block_memory_used(&self) -> usize {
+    self.blocks.len() * size_of::<Block>()
+  }
+}
+
+///Recently unloaded chunks, so walking back and forth over a chunk border doesn't regenerate them.
+///The chunks that were unloaded longest ago are dropped when it goes over its memory cap.
+pub struct ChunkCache {
+  cap: usize, //In bytes.
+  used: usize,
+  block_bytes: usize, //The part of used that is blocks rather than meshes.
+  chunks: HashMap<[i32; 3], (u64, CachedChunk)>, //Keyed by chunk ID, with the order it was added in.
+  order: BTreeMap<u64, [i32; 3]>, //Oldest first.
+  next_order: u64
+}
+
+impl ChunkCache {
+  pub fn new(cap: usize) -> Self {
+    Self {
+      cap,
+      used: 0,
+      block_bytes: 0,
+      chunks: HashMap::new(),
+      order: BTreeMap::new(),
+      next_order: 0
+    }
+  }
+
+  pub fn insert(&mut self, chunk_id: [i32; 3], chunk: CachedChunk) {
+    self.take(chunk_id);
+    let size = chunk.memory_used();
+    if size > self.cap { //Would get dropped straight away.
+      return;
+    }
+
+    self.used += size;
+    self.block_bytes += chunk.block_memory_used();
+    self.chunks.insert(chunk_id, (self.next_order, chunk));
+    self.order.insert(self.next_order, chunk_id);
+    self.next_order += 1;
+
+    while self.used > self.cap {
+      let (_, oldest) = self.order.pop_first().unwrap();
+      let (_, chunk) = self.chunks.remove(&oldest).unwrap();
+      self.used -= chunk.memory_used();
+      self.block_bytes -= chunk.block_memory_used();
+    }
+  }
+
+  ///Removes the chunk from the cache if it's there.
+  pub fn take(&mut self, chunk_id: [i32; 3]) -> Option<CachedChunk> {
+    let (order, chunk) = self.chunks.remove(&chunk_id)?;
+    self.order.remove(&order);
+    self.used -= chunk.memory_used();
+    self.block_bytes -= chunk.block_memory_used();
+    Some(chunk)
+  }
+
+  pub fn memory_used(&self) -> usize {
+    self.used
+  }
+
+  ///How much of memory_used is blocks. The rest is meshes in the mesh arena.
+  pub fn block_memory_used(&self) -> usize {
+    self.block_bytes
+  }
+
+  pub fn len(&self) -> usize {
+    self.chunks.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::world::{block::Block, chunkedterrain::CHUNK_LENGTH};
+
+  use super::{CachedChunk, ChunkCache};
+
+  fn cached() -> CachedChunk {
+    CachedChunk {
+      blocks: vec![Block::Stone; CHUNK_LENGTH],
+      mesh: None
+    }
+  }
+
+  #[test]
+  fn test_oldest_dropped() {
+    let chunk_size = cached().memory_used();
+    let mut cache = ChunkCache::new(chunk_size * 2);
+    cache.insert([0, 0, 0], cached());
+    cache.insert([1, 0, 0], cached());
+    cache.insert([2, 0, 0], cached());
+
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.memory_used(), chunk_size * 2);
+    assert!(cache.take([0, 0, 0]).is_none());
+    assert!(cache.take([1, 0, 0]).is_some());
+    assert!(cache.take([1, 0, 0]).is_none()); //Taking removes it.
+    assert_eq!(cache.memory_used(), chunk_size);
+  }
+
+  #[test]
+  fn test_reinsert_moves_to_back() {
+    let mut cache = ChunkCache::new(cached().memory_used() * 2);
+    cache.insert([0, 0, 0], cached());
+    cache.insert([1, 0, 0], cached());
+    cache.insert([0, 0, 0], cached());
+    cache.insert([2, 0, 0], cached());
+    assert!(cache.take([1, 0, 0]).is_none());
+    assert!(cache.take([0, 0, 0]).is_some());
+  }
+
+  #[test]
+  fn test_disabled() {
+    let mut cache = ChunkCache::new(0);
+    cache.insert([0, 0, 0], cached());
+    assert_eq!(cache.len(), 0);
+  }
+}
@@ -0,0 +1,266 @@
+use std::fmt;
+
+///The stages a chunk goes through before it can be drawn, in order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, PartialOrd, Ord)]
+pub enum ChunkStateStage {
+  ChunkGen,
+  ChunkVisGen,
+  MeshGen,
+  Ready
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChunkStateProgress {
+  Waiting,
+  TaskAssigned, //Activated before it is sent to another thread.
+  Processing, //When the processing is actually happening.
+  SwitchingTo(ChunkStateStage), //Can be used to interrupt the state.
+}
+
+///Where a chunk is in its lifecycle. Only changed through transitions.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChunkState {
+  stage: ChunkStateStage,
+  progress: ChunkStateProgress
+}
+
+///Things that can happen to a chunk's state.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChunkTransition {
+  Assign, //A task is being sent for the current stage.
+  Start(ChunkStateStage), //A worker is starting the task for a stage.
+  Finish(ChunkStateStage), //A worker has finished the task for a stage.
+  Revis, //The blocks changed so the visibility (and mesh) need redoing.
+}
+
+///A transition that isn't allowed from the state the chunk was in. The state is left as it was.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InvalidTransition {
+  pub from: ChunkState,
+  pub transition: ChunkTransition
+}
+
+impl ChunkStateStage {
+  pub const ALL: [ChunkStateStage; 4] = [ChunkStateStage::ChunkGen, ChunkStateStage::ChunkVisGen, ChunkStateStage::MeshGen, ChunkStateStage::Ready];
+
+  ///The stage after this one is done. Ready stays ready.
+  pub fn next(&self) -> Self {
+    match self {
+      Self::ChunkGen => Self::ChunkVisGen,
+      Self::ChunkVisGen => Self::MeshGen,
+      Self::MeshGen | Self::Ready => Self::Ready,
+    }
+  }
+}
+
+impl ChunkState {
+  pub fn new(stage: ChunkStateStage) -> Self {
+    Self {
+      stage,
+      progress: ChunkStateProgress::Waiting
+    }
+  }
+
+  pub fn stage(&self) -> ChunkStateStage {
+    self.stage
+  }
+
+  pub fn progress(&self) -> ChunkStateProgress {
+    self.progress
+  }
+
+  ///Gets the stage that needs a task sent for it, if the chunk isn't already waiting on one.
+  pub fn pending_stage(&self) -> Option<ChunkStateStage> {
+    match self.progress {
+      ChunkStateProgress::Waiting => Some(self.stage),
+      _ => None
+    }
+  }
+
+  ///True if a task has been sent for the chunk and hasn't finished yet.
+  pub fn is_busy(&self) -> bool {
+    self.progress != ChunkStateProgress::Waiting
+  }
+
+  ///Applies a transition. Returns Ok(true) if it went ahead as asked, or Ok(false) if there was nothing to do or the task was
+  ///interrupted by a revis (the chunk goes back to waiting at the new stage). Invalid transitions don't change anything.
+  pub fn apply(&mut self, transition: ChunkTransition) -> Result<bool, InvalidTransition> {
+    let invalid = InvalidTransition { from: *self, transition };
+    match transition {
+      ChunkTransition::Assign => {
+        if self.progress != ChunkStateProgress::Waiting { //Already has a task so don't send another one.
+          return Ok(false);
+        }
+        self.progress = ChunkStateProgress::TaskAssigned;
+        Ok(true)
+      },
+      ChunkTransition::Start(stage) => {
+        match self.progress {
+          ChunkStateProgress::TaskAssigned if self.stage == stage => {
+            self.progress = ChunkStateProgress::Processing;
+            Ok(true)
+          },
+          ChunkStateProgress::SwitchingTo(new_stage) if self.stage == stage => {
+            *self = Self::new(new_stage);
+            Ok(false)
+          },
+          _ => Err(invalid)
+        }
+      },
+      ChunkTransition::Finish(stage) => {
+        match self.progress {
+          ChunkStateProgress::Processing if self.stage == stage => {
+            *self = Self::new(stage.next());
+            Ok(true)
+          },
+          ChunkStateProgress::SwitchingTo(new_stage) if self.stage == stage => {
+            *self = Self::new(new_stage);
+            Ok(false)
+          },
+          _ => Err(invalid)
+        }
+      },
+      ChunkTransition::Revis => {
+        if self.stage < ChunkStateStage::ChunkVisGen { //No blocks yet so there's nothing to redo.
+          return Ok(false);
+        }
+        match self.progress {
+          ChunkStateProgress::Waiting => *self = Self::new(ChunkStateStage::ChunkVisGen),
+          ChunkStateProgress::TaskAssigned | ChunkStateProgress::Processing => {
+            self.progress = ChunkStateProgress::SwitchingTo(ChunkStateStage::ChunkVisGen);
+          },
+          ChunkStateProgress::SwitchingTo(old_switch) => {
+            self.progress = ChunkStateProgress::SwitchingTo(std::cmp::min(old_switch, ChunkStateStage::ChunkVisGen));
+          },
+        }
+        Ok(true)
+      },
+    }
+  }
+}
+
+impl fmt::Display for InvalidTransition {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "invalid chunk transition {:?} from {:?} ({:?})", self.transition, self.from.stage, self.from.progress)
+  }
+}
+
+impl std::error::Error for InvalidTransition {}
+
+#[cfg(test)]
+mod tests {
+  use super::{ChunkState, ChunkStateStage, ChunkStateProgress, ChunkTransition};
+
+  ///Tiny xorshift PRNG so the tests are repeatable without pulling in a crate.
+  struct XorShift(u64);
+
+  impl XorShift {
+    fn next(&mut self) -> u64 {
+      self.0 ^= self.0 << 13;
+      self.0 ^= self.0 >> 7;
+      self.0 ^= self.0 << 17;
+      self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+      (self.next() % n as u64) as usize
+    }
+  }
+
+  ///A task that has been sent to a worker. Started is set once the worker has begun processing it.
+  struct Task {
+    stage: ChunkStateStage,
+    started: bool
+  }
+
+  ///Does one step of the worker side of a task. Returns true if the task is done with.
+  fn step_task(state: &mut ChunkState, task: &mut Task) -> bool {
+    if task.started {
+      state.apply(ChunkTransition::Finish(task.stage)).expect("finish should be valid");
+      true
+    } else {
+      let started = state.apply(ChunkTransition::Start(task.stage)).expect("start should be valid");
+      task.started = started;
+      !started
+    }
+  }
+
+  #[test]
+  fn test_random_interleavings() {
+    let mut rng = XorShift(0x2545F4914F6CDD1D);
+    for _ in 0..200 {
+      let mut state = ChunkState::new(ChunkStateStage::ChunkGen);
+      let mut task: Option<Task> = None;
+
+      for _ in 0..100 {
+        match rng.below(3) {
+          0 => { //Terrain marks the chunk for revis (like after an edit).
+            let stage_before = state.stage();
+            state.apply(ChunkTransition::Revis).unwrap();
+            assert!(state.stage() == stage_before || state.stage() == ChunkStateStage::ChunkVisGen);
+          },
+          1 => { //Terrain tries to send a task.
+            let stage = state.stage();
+            if state.apply(ChunkTransition::Assign).unwrap() {
+              assert!(task.is_none(), "Two tasks were assigned to the same chunk.");
+              task = Some(Task { stage, started: false });
+            } else {
+              assert!(state.is_busy());
+            }
+          },
+          _ => { //The worker does the next step of its task.
+            if let Some(t) = task.as_mut() {
+              if step_task(&mut state, t) {
+                task = None;
+              }
+            }
+          },
+        }
+        assert_eq!(task.is_some(), state.is_busy()); //The chunk is only busy while a task exists for it.
+      }
+
+      //With no more revis the chunk should always make it to ready.
+      for _ in 0..20 {
+        match task.as_mut() {
+          Some(t) => if step_task(&mut state, t) {
+            task = None;
+          },
+          None => {
+            let stage = state.stage();
+            if state.apply(ChunkTransition::Assign).unwrap() {
+              task = Some(Task { stage, started: false });
+            }
+          },
+        }
+      }
+      assert_eq!(state.stage(), ChunkStateStage::Ready);
+    }
+  }
+
+  #[test]
+  fn test_invalid_transitions() {
+    let mut state = ChunkState::new(ChunkStateStage::ChunkVisGen);
+    assert!(state.apply(ChunkTransition::Start(ChunkStateStage::ChunkVisGen)).is_err()); //Not assigned.
+    assert!(state.apply(ChunkTransition::Finish(ChunkStateStage::ChunkVisGen)).is_err()); //Not processing.
+
+    state.apply(ChunkTransition::Assign).unwrap();
+    let before = state;
+    let err = state.apply(ChunkTransition::Start(ChunkStateStage::MeshGen)).unwrap_err(); //Wrong stage.
+    assert_eq!(err.from, before);
+    assert_eq!(state, before);
+
+    state.apply(ChunkTransition::Start(ChunkStateStage::ChunkVisGen)).unwrap();
+    assert!(state.apply(ChunkTransition::Start(ChunkStateStage::ChunkVisGen)).is_err()); //Already processing.
+    assert_eq!(state.progress(), ChunkStateProgress::Processing);
+  }
+
+  #[test]
+  fn test_revis_while_processing() {
+    let mut state = ChunkState::new(ChunkStateStage::MeshGen);
+    state.apply(ChunkTransition::Assign).unwrap();
+    state.apply(ChunkTransition::Start(ChunkStateStage::MeshGen)).unwrap();
+    state.apply(ChunkTransition::Revis).unwrap();
+    assert_eq!(state.apply(ChunkTransition::Finish(ChunkStateStage::MeshGen)), Ok(false)); //The mesh is already outdated.
+    assert_eq!(state, ChunkState::new(ChunkStateStage::ChunkVisGen));
+  }
+}
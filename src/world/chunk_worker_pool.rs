@@ -1,9 +1,10 @@
-use std::sync::{mpsc::Receiver, Arc, Mutex};
+use std::{sync::{Arc, Condvar, Mutex, mpsc::Sender, atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering as AtomicOrdering}}, collections::{BinaryHeap, VecDeque}, cmp::{Ordering, Reverse}, thread::{self, JoinHandle}, time::Instant};
 
 use noise::Perlin;
-use wgpu::{Queue, Device};
 
-use super::{chunk::Chunk, chunkedterrain::SurfaceHeightmap};
+use crate::renderer::mesh_uploader::MeshUploadSender;
+
+use super::{chunk::Chunk, chunkedterrain::{ColumnHeightmap, SurfaceHeightmap}, mesher::MeshAlgorithm, lod::LodTile, pipeline_stats::{PipelineStats, TaskKind}};
 
 pub enum ChunkTaskType {
   GenTerrain(Arc<Perlin>, Arc<SurfaceHeightmap>),
@@ -13,21 +14,478 @@ pub enum ChunkTaskType {
 
 pub struct ChunkTask {
   pub chunk: Arc<Chunk>,
-  pub typ: ChunkTaskType
-}
-
-pub fn run_worker_thread(device: Arc<Device>, queue: Arc<Queue>, chunk_reciever: Arc<Mutex<Receiver<ChunkTask>>>) {
-  'thread_loop: loop {
-    let recieved = chunk_reciever.lock().unwrap().recv();
-    match recieved {
-      Ok(task) => {
-        match task.typ {
-            ChunkTaskType::GenTerrain(gen, surface_heightmap) => task.chunk.gen(&gen, &surface_heightmap),
-            ChunkTaskType::GenBlockVis(surrounding_chunks) => task.chunk.gen_block_vis(surrounding_chunks),
-            ChunkTaskType::GenVertices => task.chunk.update_vertices(&device, &queue),
+  pub typ: ChunkTaskType,
+  pub events: Sender<ChunkEvent> //Tells the terrain when the task changes something other chunks care about.
+}
+
+///Generates the heightmap of a column of chunks. None of the chunks in the column can generate until this is done.
+pub struct HeightmapTask {
+  pub column: Arc<ColumnHeightmap>,
+  pub gen: Arc<Perlin>,
+  pub events: Sender<ChunkEvent>
+}
+
+///Generates an LOD tile and sends its mesh to be uploaded.
+pub struct LodTask {
+  pub tile: Arc<LodTile>,
+  pub gen: Arc<Perlin>
+}
+
+///Anything the workers can do.
+pub enum WorkerTask {
+  Chunk(ChunkTask),
+  Heightmap(HeightmapTask),
+  Lod(LodTask)
+}
+
+///Sent from the workers back to the terrain.
+pub enum ChunkEvent {
+  Generated([i32; 3]), //The blocks of a chunk have been generated.
+  TaskFinished([i32; 3]), //A task for the chunk has finished, so it might be ready for its next stage.
+  HeightmapReady([i32; 2]), //The heightmap of a column (by chunk x and z) has been generated, so its chunks can generate.
+}
+
+///Chunk tasks waiting for a worker. The task with the lowest priority value gets done first.
+///Each worker has its own heap of tasks, and new tasks are spread across the heaps in turn. Workers only lock another worker's heap to steal
+///from it when their own is empty, and reprioritising locks one heap at a time, so there isn't one lock that every task has to go through.
+///Each worker does its own tasks in order, so the order across all workers is only roughly by priority.
+pub struct ChunkTaskQueue {
+  heaps: Vec<Mutex<BinaryHeap<QueuedTask>>>, //One per worker.
+  next_heap: AtomicUsize, //The heap the next task is pushed to.
+  next_order: AtomicU64, //Tasks with the same priority are done in the order they were added.
+  len: AtomicUsize, //Tasks in all of the heaps.
+  closed: AtomicBool,
+  sleeping: AtomicUsize, //Workers waiting for tasks. Pushes only take the sleep lock to wake them if there are any.
+  sleep_lock: Mutex<()>,
+  task_available: Condvar,
+  stats: PipelineStats
+}
+
+///Threads that process chunk tasks.
+pub struct ChunkWorkerPool {
+  task_queue: Arc<ChunkTaskQueue>,
+  workers: Vec<JoinHandle<()>>
+}
+
+struct QueuedTask {
+  priority: u32,
+  order: u64,
+  task: WorkerTask
+}
+
+impl ChunkTaskQueue {
+  ///Makes a queue for `worker_count` workers.
+  pub fn new(worker_count: usize) -> Self {
+    Self {
+      heaps: (0..worker_count.max(1)).map(|_| Mutex::new(BinaryHeap::new())).collect(),
+      next_heap: AtomicUsize::new(0),
+      next_order: AtomicU64::new(0),
+      len: AtomicUsize::new(0),
+      closed: AtomicBool::new(false),
+      sleeping: AtomicUsize::new(0),
+      sleep_lock: Mutex::new(()),
+      task_available: Condvar::new(),
+      stats: PipelineStats::new()
+    }
+  }
+
+  pub fn push<T: Into<WorkerTask>>(&self, task: T, priority: u32) {
+    let heap_index = self.next_heap.fetch_add(1, AtomicOrdering::Relaxed) % self.heaps.len();
+    {
+      let mut heap = self.heaps[heap_index].lock().unwrap();
+      if self.closed.load(AtomicOrdering::SeqCst) { //The workers have stopped.
+        return;
+      }
+      let task = task.into();
+      self.stats.task_queued(task.kind());
+      heap.push(QueuedTask { priority, order: self.next_order.fetch_add(1, AtomicOrdering::Relaxed), task });
+    }
+    self.len.fetch_add(1, AtomicOrdering::SeqCst);
+    if self.sleeping.load(AtomicOrdering::SeqCst) > 0 {
+      let _sleep_lock = self.sleep_lock.lock().unwrap();
+      self.task_available.notify_one();
+    }
+  }
+
+  ///Moves up to `max` of the most important tasks from all of the heaps into `out`. Returns the number of tasks moved.
+  pub fn pop_batch(&self, max: usize, out: &mut VecDeque<WorkerTask>) -> usize {
+    let mut heaps: Vec<_> = self.heaps.iter().map(|heap| heap.lock().unwrap()).collect();
+    let mut count = 0;
+    while count < max {
+      let best = heaps.iter().enumerate()
+        .filter_map(|(i, heap)| Some((i, heap.peek()?)))
+        .max_by(|(_, a), (_, b)| a.cmp(b))
+        .map(|(i, _)| i);
+      let queued = match best.and_then(|i| heaps[i].pop()) {
+        Some(queued) => queued,
+        None => break,
+      };
+      self.len.fetch_sub(1, AtomicOrdering::SeqCst);
+      self.stats.task_dequeued(queued.task.kind());
+      out.push_back(queued.task);
+      count += 1;
+    }
+    count
+  }
+
+  ///Takes the most important task from one of the heaps.
+  fn pop_from(&self, heap_index: usize) -> Option<WorkerTask> {
+    let queued = self.heaps[heap_index].lock().unwrap().pop()?;
+    self.len.fetch_sub(1, AtomicOrdering::SeqCst);
+    self.stats.task_dequeued(queued.task.kind());
+    Some(queued.task)
+  }
+
+  ///Gets the next task for a worker from its own heap, or steals one from another worker's heap. Waits if there is nothing to do.
+  ///Returns None when the queue has been closed.
+  fn next_task(&self, worker: usize) -> Option<WorkerTask> {
+    loop {
+      let heap_count = self.heaps.len();
+      if let Some(task) = (0..heap_count).find_map(|offset| self.pop_from((worker + offset) % heap_count)) {
+        return Some(task);
+      }
+
+      if !self.wait_for_tasks() {
+        return None;
+      }
+    }
+  }
+
+  ///Blocks until there are tasks in the queue. Returns false if the queue has been closed.
+  pub fn wait_for_tasks(&self) -> bool {
+    let sleep_lock = self.sleep_lock.lock().unwrap();
+    //Counted before checking for tasks, so a push either sees the sleeping worker and wakes it, or happened first and gets seen here.
+    self.sleeping.fetch_add(1, AtomicOrdering::SeqCst);
+    let _sleep_lock = self.task_available.wait_while(sleep_lock, |_| {
+      self.len.load(AtomicOrdering::SeqCst) == 0 && !self.closed.load(AtomicOrdering::SeqCst)
+    }).unwrap();
+    self.sleeping.fetch_sub(1, AtomicOrdering::SeqCst);
+    !self.closed.load(AtomicOrdering::SeqCst)
+  }
+
+  ///Stops accepting tasks, drops the queued ones and wakes up any waiting workers so they can exit.
+  pub fn close(&self) {
+    self.closed.store(true, AtomicOrdering::SeqCst);
+    for heap in &self.heaps {
+      for queued in heap.lock().unwrap().drain() {
+        self.len.fetch_sub(1, AtomicOrdering::SeqCst);
+        self.stats.task_dequeued(queued.task.kind());
+      }
+    }
+    let _sleep_lock = self.sleep_lock.lock().unwrap();
+    self.task_available.notify_all();
+  }
+
+  ///Recalculates the priority of every queued task, one heap at a time. Tasks are dropped if `get_priority` returns None.
+  pub fn reprioritise<F: FnMut(&WorkerTask) -> Option<u32>>(&self, mut get_priority: F) {
+    for heap in &self.heaps {
+      let mut heap = heap.lock().unwrap();
+      *heap = std::mem::take(&mut *heap).into_iter().filter_map(|mut queued| {
+        match get_priority(&queued.task) {
+          Some(priority) => queued.priority = priority,
+          None => {
+            self.len.fetch_sub(1, AtomicOrdering::SeqCst);
+            self.stats.task_dequeued(queued.task.kind());
+            return None;
+          }
         }
+        Some(queued)
+      }).collect();
+    }
+  }
+
+  ///How many tasks are waiting.
+  pub fn len(&self) -> usize {
+    self.len.load(AtomicOrdering::SeqCst)
+  }
+
+  pub fn get_stats(&self) -> &PipelineStats {
+    &self.stats
+  }
+}
+
+impl WorkerTask {
+  ///Tasks for things that have been unloaded don't need doing.
+  pub fn is_cancelled(&self) -> bool {
+    match self {
+      WorkerTask::Chunk(task) => task.chunk.is_cancelled(),
+      WorkerTask::Heightmap(task) => task.column.is_cancelled(),
+      WorkerTask::Lod(task) => task.tile.is_cancelled(),
+    }
+  }
+
+  pub fn kind(&self) -> TaskKind {
+    match self {
+      WorkerTask::Chunk(task) => match task.typ {
+        ChunkTaskType::GenTerrain(..) => TaskKind::ChunkGen,
+        ChunkTaskType::GenBlockVis(_) => TaskKind::ChunkVisGen,
+        ChunkTaskType::GenVertices => TaskKind::MeshGen,
+      },
+      WorkerTask::Heightmap(_) => TaskKind::Heightmap,
+      WorkerTask::Lod(_) => TaskKind::LodTile,
+    }
+  }
+
+  ///The chunk x and z of the column the task is in.
+  pub fn column(&self) -> [i32; 2] {
+    match self {
+      WorkerTask::Chunk(task) => {
+        let [x, _, z] = task.chunk.get_id();
+        [x, z]
       },
-      Err(_) => break 'thread_loop,
+      WorkerTask::Heightmap(task) => task.column.get_column_pos(),
+      WorkerTask::Lod(task) => task.tile.get_id().centre_column(),
+    }
+  }
+}
+
+impl From<ChunkTask> for WorkerTask {
+  fn from(task: ChunkTask) -> Self {
+    WorkerTask::Chunk(task)
+  }
+}
+
+impl From<HeightmapTask> for WorkerTask {
+  fn from(task: HeightmapTask) -> Self {
+    WorkerTask::Heightmap(task)
+  }
+}
+
+impl From<LodTask> for WorkerTask {
+  fn from(task: LodTask) -> Self {
+    WorkerTask::Lod(task)
+  }
+}
+
+impl HeightmapTask {
+  ///Generates the heightmap and tells the terrain it's ready.
+  pub fn run(self) {
+    if self.column.generate(&self.gen) {
+      let _ = self.events.send(ChunkEvent::HeightmapReady(self.column.get_column_pos())); //The terrain might have been dropped.
+    }
+  }
+}
+
+impl LodTask {
+  pub fn run(self, mesh_uploads: &MeshUploadSender) {
+    if self.tile.generate(&self.gen) {
+      let _ = mesh_uploads.send(self.tile); //The renderer might have been dropped.
     }
   }
-}
\ No newline at end of file
+}
+
+impl QueuedTask {
+  fn key(&self) -> (Reverse<u32>, Reverse<u64>) { //BinaryHeap is a max heap so reverse the order.
+    (Reverse(self.priority), Reverse(self.order))
+  }
+}
+
+impl PartialEq for QueuedTask {
+  fn eq(&self, other: &Self) -> bool {
+    self.key() == other.key()
+  }
+}
+
+impl Eq for QueuedTask {}
+
+impl PartialOrd for QueuedTask {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for QueuedTask {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self.key().cmp(&other.key())
+  }
+}
+
+impl ChunkWorkerPool {
+  ///Meshes that the workers build get sent to `mesh_uploads` so they can be uploaded on the render thread.
+  pub fn new(thread_count: usize, mesh_algorithm: MeshAlgorithm, mesh_uploads: MeshUploadSender) -> Self {
+    let task_queue = Arc::new(ChunkTaskQueue::new(thread_count));
+
+    let workers = (0..thread_count).map(|i| {
+      let (task_queue, mesh_uploads) = (task_queue.clone(), mesh_uploads.clone());
+      thread::Builder::new().name(format!("Worker #{}", i)).spawn(move || {
+        run_worker_thread(i, &task_queue, mesh_algorithm, &mesh_uploads)
+      }).unwrap()
+    }).collect();
+
+    Self {
+      task_queue,
+      workers
+    }
+  }
+
+  pub fn get_task_queue(&self) -> Arc<ChunkTaskQueue> {
+    self.task_queue.clone()
+  }
+
+  ///Stops the workers once they have finished their current task and waits for them to exit.
+  pub fn shutdown(self) {
+    self.task_queue.close();
+    for worker in self.workers {
+      if worker.join().is_err() {
+        eprintln!("Chunk worker panicked.");
+      }
+    }
+  }
+}
+
+fn run_worker_thread(worker: usize, task_queue: &ChunkTaskQueue, mesh_algorithm: MeshAlgorithm, mesh_uploads: &MeshUploadSender) {
+  while let Some(task) = task_queue.next_task(worker) {
+    if task.is_cancelled() { //The chunk was unloaded while the task was queued.
+      continue;
+    }
+    let (stats, kind) = (task_queue.get_stats(), task.kind());
+    stats.start_task(kind);
+    let start = Instant::now();
+    match task {
+      WorkerTask::Chunk(task) => run_chunk_task(task, mesh_algorithm, mesh_uploads),
+      WorkerTask::Heightmap(task) => task.run(),
+      WorkerTask::Lod(task) => task.run(mesh_uploads),
+    }
+    stats.finish_task(kind, start.elapsed());
+  }
+}
+
+fn run_chunk_task(task: ChunkTask, mesh_algorithm: MeshAlgorithm, mesh_uploads: &MeshUploadSender) {
+  match task.typ {
+      ChunkTaskType::GenTerrain(gen, surface_heightmap) => {
+        if task.chunk.gen(&gen, &surface_heightmap) {
+          let _ = task.events.send(ChunkEvent::Generated(task.chunk.get_id())); //The terrain might have been dropped.
+        }
+      },
+      ChunkTaskType::GenBlockVis(surrounding_chunks) => task.chunk.gen_block_vis(surrounding_chunks),
+      ChunkTaskType::GenVertices => {
+        if task.chunk.gen_mesh(mesh_algorithm) {
+          let _ = mesh_uploads.send(task.chunk.clone()); //The renderer might have been dropped.
+        }
+      },
+  };
+  let _ = task.events.send(ChunkEvent::TaskFinished(task.chunk.get_id()));
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::{Arc, mpsc::channel}, collections::VecDeque};
+
+  use crate::world::{chunk::Chunk, pipeline_stats::TaskKind};
+
+  use super::{ChunkTask, ChunkTaskQueue, ChunkTaskType};
+
+  fn task(x: i32) -> ChunkTask {
+    ChunkTask {
+      chunk: Arc::new(Chunk::new([x, 0, 0])),
+      typ: ChunkTaskType::GenVertices,
+      events: channel().0
+    }
+  }
+
+  #[test]
+  fn test_queue_order() {
+    let queue = ChunkTaskQueue::new(1);
+    queue.push(task(0), 5);
+    queue.push(task(1), 1);
+    queue.push(task(2), 5);
+    queue.push(task(3), 0);
+
+    let mut batch = VecDeque::new();
+    assert_eq!(queue.pop_batch(10, &mut batch), 4);
+    let order: Vec<i32> = batch.iter().map(|task| task.column()[0]).collect();
+    assert_eq!(order, vec![3, 1, 0, 2]); //Same priorities keep the order they were added in.
+  }
+
+  #[test]
+  fn test_queue_reprioritise() {
+    let queue = ChunkTaskQueue::new(1);
+    for x in 0..4 {
+      queue.push(task(x), x as u32);
+    }
+
+    //Reverse the order and drop chunk 1.
+    queue.reprioritise(|task| {
+      let x = task.column()[0];
+      if x == 1 {None} else {Some(10 - x as u32)}
+    });
+
+    let mut batch = VecDeque::new();
+    assert_eq!(queue.pop_batch(2, &mut batch), 2);
+    assert_eq!(queue.len(), 1);
+    let order: Vec<i32> = batch.iter().map(|task| task.column()[0]).collect();
+    assert_eq!(order, vec![3, 2]);
+  }
+
+  #[test]
+  fn test_queue_drop_cancelled() {
+    let queue = ChunkTaskQueue::new(1);
+    let tasks = (0..4).map(task).collect::<Vec<_>>();
+    tasks[0].chunk.cancel();
+    tasks[2].chunk.cancel();
+    for task in tasks {
+      queue.push(task, 0);
+    }
+
+    queue.reprioritise(|task| if task.is_cancelled() {None} else {Some(0)});
+    assert_eq!(queue.len(), 2);
+  }
+
+  #[test]
+  fn test_queue_close() {
+    let queue = ChunkTaskQueue::new(1);
+    queue.push(task(0), 0);
+    assert!(queue.wait_for_tasks());
+
+    queue.close();
+    assert!(!queue.wait_for_tasks()); //Doesn't block once closed.
+    queue.push(task(1), 0);
+    assert_eq!(queue.len(), 0);
+  }
+
+  #[test]
+  fn test_worker_heaps() {
+    let queue = ChunkTaskQueue::new(2);
+    for x in 0..6 {
+      queue.push(task(x), x as u32); //Worker 0 gets the even chunks and worker 1 gets the odd ones.
+    }
+
+    //Reverse the order and drop chunk 1.
+    queue.reprioritise(|task| {
+      let x = task.column()[0];
+      if x == 1 {None} else {Some(10 - x as u32)}
+    });
+    assert_eq!(queue.len(), 5);
+
+    //Each worker does its own tasks first.
+    assert_eq!(queue.next_task(0).map(|task| task.column()[0]), Some(4));
+    assert_eq!(queue.next_task(1).map(|task| task.column()[0]), Some(5));
+    assert_eq!(queue.next_task(0).map(|task| task.column()[0]), Some(2));
+    assert_eq!(queue.next_task(0).map(|task| task.column()[0]), Some(0));
+    //Then steals from the other worker.
+    assert_eq!(queue.next_task(0).map(|task| task.column()[0]), Some(3));
+    assert_eq!(queue.len(), 0);
+
+    queue.push(task(6), 0);
+    queue.close();
+    assert_eq!(queue.len(), 0);
+    assert!(queue.next_task(0).is_none());
+  }
+
+  #[test]
+  fn test_queued_counts() {
+    let queue = ChunkTaskQueue::new(2);
+    let queued = |queue: &ChunkTaskQueue| queue.get_stats().snapshot()[TaskKind::MeshGen as usize].queued;
+    for x in 0..6 {
+      queue.push(task(x), 0);
+    }
+    assert_eq!(queued(&queue), 6);
+
+    queue.reprioritise(|task| if task.column()[0] == 1 {None} else {Some(0)});
+    queue.next_task(0);
+    assert_eq!(queued(&queue), 4);
+
+    queue.close();
+    assert_eq!(queued(&queue), 0);
+  }
+}
@@ -1,4 +1,4 @@
-use std::{ops::Range, sync::{Arc, mpsc::Sender}, mem, cmp::Ordering};
+use std::{ops::{Range, RangeInclusive}, sync::{Arc, OnceLock, atomic::{AtomicBool, Ordering}, mpsc::{channel, Receiver, Sender}}, mem, collections::{VecDeque, HashMap, HashSet}};
 
 use cgmath::{num_traits::Signed, InnerSpace, Vector3};
 use fixed::traits::Fixed;
@@ -6,26 +6,54 @@ use itertools::iproduct;
 use noise::{Perlin, NoiseFn, Seedable};
 use num_iter::{range_step_inclusive, RangeStepInclusive};
 
-use crate::{world::chunk::ChunkDataView, util::{FPVector, Fixed64}};
+use crate::{world::chunk::ChunkDataView, util::{FPVector, Fixed64}, renderer::mesh_arena::MeshMemoryCounter};
 
-use super::{chunk::{Chunk, ChunkMeshData, ChunkStateStage, ADJACENT_OFFSETS}, player::HitBox, chunk_worker_pool::{ChunkTask, ChunkTaskType}, block::{Block, BlockSide, BlockSideVisibility}};
+use super::{chunk::{Chunk, ChunkMeshRange, ADJACENT_OFFSETS}, chunk_state::ChunkStateStage, player::HitBox, chunk_worker_pool::{ChunkTask, ChunkTaskType, ChunkTaskQueue, ChunkEvent, HeightmapTask, LodTask, WorkerTask}, block::{Block, BlockSide, BlockSideVisibility}, raycast::{cast_ray, RaycastHit}, edit::{EditJob, EditOrigin, EditRecord, Region, RegionOperation}, load_area::LoadArea, visibility::ChunkConnectivity, lod::{LodTile, LodTileId, select_lod_tiles}, chunk_cache::{CachedChunk, ChunkCache}, pipeline_stats::PipelineSnapshot, memory_budget::{fit_radius, MemoryBudget, MemoryUsage}};
 
 pub const CHUNK_SIZE: usize = 32;
 pub const CHUNK_SIZE_I32: i32 = CHUNK_SIZE as i32;
 pub const HEIGHTMAP_SIZE: usize = CHUNK_SIZE*CHUNK_SIZE;
 pub const CHUNK_LENGTH: usize = HEIGHTMAP_SIZE*CHUNK_SIZE;
 pub const CHUNK_RANGE: Range<usize> = 0..CHUNK_SIZE;
+const EDIT_CHUNKS_PER_TICK: usize = 8; //The maximum number of chunks that region edits can change per tick.
+const VIEW_COS: f32 = 0.5; //Chunks within about 60 degrees of where the player is looking are in view.
+const OUT_OF_VIEW_PENALTY: i32 = 4; //Chunks that aren't in view are treated as being twice as far away.
+const REPRIORITISE_COS: f32 = 0.87; //Reprioritise tasks when the player turns more than about 30 degrees.
 
 pub type SurfaceHeightmap = [i32; HEIGHTMAP_SIZE];
 
+///What gets loaded around the player and how much memory it can use.
+pub struct TerrainSettings {
+  pub load_area: LoadArea,
+  pub lod_levels: u8,
+  pub chunk_cache: ChunkCache, //Unloaded chunks that can be loaded again without generating them.
+  pub memory_budget: MemoryBudget
+}
+
 pub struct ChunkedTerrain {
-  columns: Vec<ChunkColumn>, //Sorted in x ascending, then z ascending,
-  chunk_id_bounds: [[i32; 3]; 2],
+  columns: HashMap<[i32; 2], ChunkColumn>, //Keyed by chunk x and z.
   player_last_chunk_id: Vector3<i32>, //The last Chunk ID of the player.
-  render_distance: u32,
-  worker_pool_sender: Sender<ChunkTask>,
+  load_area: LoadArea,
+  load_area_changed: bool, //Set when the load area changes so the loaded chunks get updated even if the player hasn't moved.
+  task_queue: Arc<ChunkTaskQueue>,
+  look_direction: Vector3<f32>,
+  prioritised_view: (Vector3<i32>, Vector3<f32>), //Player chunk and look direction the queued tasks were last prioritised for.
   gen: Arc<Perlin>,
-  chunk_gc: Sender<Arc<Chunk>>
+  chunk_gc: Sender<Arc<Chunk>>,
+  event_sender: Sender<ChunkEvent>, //Cloned into every task.
+  events: Receiver<ChunkEvent>,
+  ready: HashSet<[i32; 3]>, //Chunks that might be waiting for their next task. Only these get checked each tick.
+  deferred: HashSet<[i32; 3]>, //Chunks waiting for vis gen until their neighbours have generated.
+  edit_queue: VecDeque<EditJob>,
+  finished_edits: Vec<(EditOrigin, EditRecord)>,
+  saved_chunks: HashMap<[i32; 3], Vec<Block>>, //Blocks of modified chunks that have been unloaded, so edits aren't lost when they are loaded again.
+  chunk_cache: ChunkCache, //Unmodified chunks that were unloaded recently. Unlike saved_chunks, these can be dropped.
+  lod_levels: u8,
+  lod_tiles: HashMap<LodTileId, Arc<LodTile>>, //Includes tiles that the workers haven't generated yet.
+  memory_budget: MemoryBudget,
+  mesh_memory: MeshMemoryCounter, //Everything in the mesh arena.
+  memory_usage: MemoryUsage, //As of the last tick.
+  budget_radius: u32 //The render distance that fits in the memory budget. Only used if it's smaller than the load area's radius.
 }
 
 
@@ -36,164 +64,209 @@ struct BlockArea {
 }
 
 impl ChunkedTerrain {
-  pub fn new(player_position: FPVector, render_distance: u32, worker_pool_sender: Sender<ChunkTask>, chunk_gc: Sender<Arc<Chunk>>) -> Self {
+  pub fn new(player_position: FPVector, settings: TerrainSettings, mesh_memory: MeshMemoryCounter, task_queue: Arc<ChunkTaskQueue>, chunk_gc: Sender<Arc<Chunk>>) -> Self {
+    let TerrainSettings { load_area, lod_levels, chunk_cache, memory_budget } = settings;
     let player_chunk_id = Self::pos_to_chunk_id(player_position.get_int());
-    let chunk_id_bounds: [[i32; 3]; 2] = [
-      player_chunk_id.map(|chk| chk-render_distance as i32).into(),
-      player_chunk_id.map(|chk| chk+render_distance as i32).into()
-    ];
-    
     let gen = Arc::new(Perlin::new().set_seed(7355608));
+    let (event_sender, events) = channel();
     
-    let columns: Vec<ChunkColumn> = iproduct!(
-      chunk_id_bounds[0][0]..chunk_id_bounds[1][0], 
-      chunk_id_bounds[0][2]..chunk_id_bounds[1][2])
-      .map(|(cx, cz)| {
-        let mut column = ChunkColumn::new(&gen, [cx, cz]);
-        for cy in chunk_id_bounds[0][1]..chunk_id_bounds[1][1] { //Iterate vertically
-          column.chunks.push(make_new_chunk([cx, cy, cz]));
-        }
-        column
-      }).collect();
-    
-    
-    Self {
-      columns,
-      render_distance,
-      worker_pool_sender,
-      chunk_id_bounds,
-      player_last_chunk_id: player_chunk_id.into(),
+    let mut terrain = Self {
+      columns: HashMap::new(),
+      load_area,
+      load_area_changed: false,
+      task_queue,
+      look_direction: Vector3::new(0.0, 0.0, 1.0),
+      prioritised_view: (player_chunk_id, Vector3::new(0.0, 0.0, 1.0)),
+      player_last_chunk_id: player_chunk_id,
       gen,
-      chunk_gc
-    }
+      chunk_gc,
+      event_sender,
+      events,
+      ready: HashSet::new(),
+      deferred: HashSet::new(),
+      edit_queue: VecDeque::new(),
+      finished_edits: Vec::new(),
+      saved_chunks: HashMap::new(),
+      chunk_cache,
+      lod_levels,
+      lod_tiles: HashMap::new(),
+      memory_budget,
+      mesh_memory,
+      memory_usage: MemoryUsage::default(),
+      budget_radius: load_area.radius
+    };
+    terrain.update_loaded_chunks();
+    terrain
   }
 
   //Returns true if the chunk vertices need to be regenerated.
-  pub fn update_player_position(&mut self, player_position: &FPVector) -> bool { //Similar to the new() function but uses existing chunks if necessary.
+  pub fn update_player_position(&mut self, player_position: &FPVector) -> bool {
     let player_chunk_id = Self::pos_to_chunk_id(player_position.get_int());
-    if player_chunk_id == self.player_last_chunk_id { //Skip the update if the player has not moved far enough to update the world.
+    if player_chunk_id == self.player_last_chunk_id && !self.load_area_changed { //Skip the update if the player has not moved far enough to update the world.
       return false;
     }
 
-    let new_bounds: [[i32; 3]; 2] = [
-      player_chunk_id.map(|chk| chk-self.render_distance as i32).into(),
-      player_chunk_id.map(|chk| chk+self.render_distance as i32).into()
-    ];
+    self.player_last_chunk_id = player_chunk_id;
+    self.load_area_changed = false;
+    self.update_loaded_chunks();
+    true
+  }
 
-    let needs_regen = [ //Compare new bounds to old bounds to see if edges of chunks need regenerating.
-      (new_bounds[0][0] < self.chunk_id_bounds[0][0], new_bounds[1][0] > self.chunk_id_bounds[1][0]), //X expansion,
-      (new_bounds[0][1] < self.chunk_id_bounds[0][1], new_bounds[1][1] > self.chunk_id_bounds[1][1]), //Y expansion
-      (new_bounds[0][2] < self.chunk_id_bounds[0][2], new_bounds[1][2] > self.chunk_id_bounds[1][2]), //Z expansion
-    ];
+  ///Changes which chunks are loaded around the player. The chunks get loaded and unloaded on the next update.
+  pub fn set_load_area(&mut self, load_area: LoadArea) {
+    if load_area != self.load_area {
+      self.load_area = load_area;
+      self.budget_radius = load_area.radius; //Shrunk again on the next tick if it doesn't fit.
+      self.load_area_changed = true;
+    }
+  }
 
+  pub fn get_load_area(&self) -> LoadArea {
+    self.load_area
+  }
 
-    let regen = [0, 1, 2].map(|i| {
-      let (a, b) = (needs_regen[i].0, needs_regen[i].1);
-      if a && b {
-        ChunkRegenCoord::Both(self.chunk_id_bounds[0][i], self.chunk_id_bounds[1][i] - 1)
-      } else if a { //Value is decreasing
-        ChunkRegenCoord::One(self.chunk_id_bounds[0][i])
-      } else if b { //Value is increasing
-        ChunkRegenCoord::One(self.chunk_id_bounds[1][i] - 1) //Chunk high bounds are exclusive so subtract 1.
-      } else {
-        ChunkRegenCoord::None
+  ///The load area with the render distance shrunk to fit in the memory budget.
+  pub fn get_effective_load_area(&self) -> LoadArea {
+    LoadArea {
+      radius: self.load_area.radius.min(self.budget_radius),
+      ..self.load_area
+    }
+  }
+
+  ///Measures how much memory the loaded chunks use and changes the render distance so they fit in the budget.
+  pub fn tick_memory_budget(&mut self) {
+    let mut usage = MemoryUsage::default();
+    for chunk in self.columns.values().flat_map(|column| column.chunks.iter()) {
+      let (blocks, mesh) = chunk.memory_used();
+      if let Some(blocks) = blocks {
+        usage.loaded_blocks += blocks;
+        usage.chunks_with_blocks += 1;
       }
-    });
-    
+      if let Some(mesh) = mesh {
+        usage.loaded_meshes += mesh;
+        usage.chunks_with_meshes += 1;
+      }
+    }
+    let saved_blocks: usize = self.saved_chunks.values().map(|blocks| blocks.len() * mem::size_of::<Block>()).sum();
+    usage.blocks = usage.loaded_blocks + saved_blocks + self.chunk_cache.block_memory_used();
+    usage.meshes = self.mesh_memory.load(Ordering::Relaxed); //Includes LOD tiles and cached chunks.
+    self.memory_usage = usage;
+
+    let old_radius = self.get_effective_load_area().radius;
+    self.budget_radius = fit_radius(&self.load_area, old_radius, &usage, &self.memory_budget);
+    let new_radius = self.get_effective_load_area().radius;
+    if new_radius != old_radius {
+      if new_radius < old_radius && old_radius == self.load_area.radius { //Only warn when it first goes over, the debug menu shows the rest.
+        eprintln!("Over the chunk memory budget, so the render distance has been reduced.");
+      }
+      self.load_area_changed = true;
+    }
+  }
 
-    let new_columns = Vec::with_capacity(self.render_distance.pow(2) as usize * 2); //Estimate columns by squaring the render distance
-    let old_columns = mem::replace(&mut self.columns, new_columns); //Replace the old column list with the new one.
+  pub fn get_memory_usage(&self) -> MemoryUsage {
+    self.memory_usage
+  }
 
-    let mut old_column_iter = iproduct!( //Create an iterator of old columns.
-      self.chunk_id_bounds[0][0]..self.chunk_id_bounds[1][0],
-      self.chunk_id_bounds[0][2]..self.chunk_id_bounds[1][2]
-    ).zip(old_columns.into_iter());
-    let mut next_old_column = old_column_iter.next(); //Store the next old column.
-    
-    
-    for (ncx, ncz) in iproduct!(
-      new_bounds[0][0]..new_bounds[1][0],
-      new_bounds[0][2]..new_bounds[1][2]
-    ) {
-      while matches!(&next_old_column, Some(col) if matches!([col.0.0, col.0.1].cmp(&[ncx, ncz]), Ordering::Less)) { //Skip columns that have already passed.
-        for chunk in next_old_column.unwrap().1.chunks { //Send chunks to gc so deleting them doesn't block this thread.
-          self.chunk_gc.send(chunk).unwrap();
-        }
-        next_old_column = old_column_iter.next();
-      }
+  pub fn get_memory_budget(&self) -> MemoryBudget {
+    self.memory_budget
+  }
+
+  ///Loads and unloads chunks so the loaded chunks match the load area around the player. Chunks that are already loaded and still in the area are kept.
+  fn update_loaded_chunks(&mut self) {
+    let centre = self.player_last_chunk_id;
+
+    let load_area = self.get_effective_load_area();
 
-      let column = match &next_old_column {
-        //Column already exists.
-        Some(col) if [col.0.0, col.0.1] == [ncx, ncz] => {
-          let mut column = next_old_column.unwrap().1;
-          next_old_column = old_column_iter.next();
-          self.reuse_column(&mut column, new_bounds, [ncx, ncz], regen);
-          column
+    //Unload or resize the columns that are already loaded. The columns furthest away are the ones that get unloaded when the area shrinks.
+    for (column_pos, mut column) in mem::take(&mut self.columns) {
+      match load_area.column_range(column_pos[0] - centre.x, column_pos[1] - centre.z) {
+        Some(range) => {
+          let range = (range.start() + centre.y)..=(range.end() + centre.y);
+          self.resize_column(&mut column, column_pos, range);
+          self.columns.insert(column_pos, column);
         },
-        //Create new column.
-        _ => {
-          let mut column = ChunkColumn::new(&self.gen, [ncx, ncz]);
-          for ncy in new_bounds[0][1]..new_bounds[1][1] {
-            let chunk = make_new_chunk([ncx, ncy, ncz]);
-            column.chunks.push(chunk);
+        None => {
+          column.height_map.cancel();
+          for chunk in column.chunks {
+            self.unload_chunk(chunk);
           }
-          column
         },
-      };
-      self.columns.push(column);
+      }
     }
 
-    self.chunk_id_bounds = new_bounds; //Update bounds.
-    self.player_last_chunk_id = player_chunk_id;
+    //Create columns that aren't loaded yet.
+    let missing_columns = load_area.columns()
+      .map(|([dx, dz], range)| ([centre.x + dx, centre.z + dz], (range.start() + centre.y)..=(range.end() + centre.y)))
+      .filter(|(column_pos, _)| !self.columns.contains_key(column_pos))
+      .collect::<Vec<_>>();
+    for (column_pos, range) in missing_columns {
+      let mut column = ChunkColumn::new(column_pos, *range.start());
+      for cy in range {
+        column.chunks.push(self.load_chunk([column_pos[0], cy, column_pos[1]]));
+      }
+      self.columns.insert(column_pos, column);
+    }
 
-    true
+    //Deferred chunks might have been waiting on neighbours that are now unloaded.
+    let deferred = mem::take(&mut self.deferred);
+    self.ready.extend(deferred);
+
+    self.update_lod_tiles();
+  }
+
+  ///Drops the LOD tiles that aren't needed around the player any more and queues the missing ones.
+  fn update_lod_tiles(&mut self) {
+    let centre = self.player_last_chunk_id;
+    let wanted = select_lod_tiles(&self.get_effective_load_area(), [centre.x, centre.z], self.lod_levels);
+    self.lod_tiles.retain(|id, tile| {
+      let keep = wanted.contains(id);
+      if !keep {
+        tile.cancel();
+      }
+      keep
+    });
+
+    //The workers generate them. Tiles further out get a lower priority because they're further away.
+    for id in wanted {
+      if !self.lod_tiles.contains_key(&id) {
+        let tile = Arc::new(LodTile::new(id));
+        let task = WorkerTask::Lod(LodTask { tile: tile.clone(), gen: self.gen.clone() });
+        let priority = Self::worker_task_priority(&task, self.player_last_chunk_id, self.look_direction);
+        self.task_queue.push(task, priority);
+        self.lod_tiles.insert(id, tile);
+      }
+    }
+  }
+
+  ///Gets the block origin, cell scale and mesh of every LOD tile that has been uploaded.
+  pub fn get_lod_meshes(&self) -> Vec<([i32; 3], i32, ChunkMeshRange)> {
+    self.lod_tiles.values()
+      .filter_map(|tile| Some((tile.get_origin()?, tile.get_id().scale(), tile.get_mesh_fast()?)))
+      .collect()
   }
 
-  pub fn get_meshes(&self) -> Vec<([i32; 3], ChunkMeshData)> {
-    let mut meshes = Vec::new();
-    for col in self.columns.iter() {
+  ///Gets every loaded chunk's connectivity (for cave culling) and its mesh if it has one.
+  pub fn get_render_chunks(&self) -> Vec<([i32; 3], ChunkConnectivity, Option<ChunkMeshRange>)> {
+    let mut chunks = Vec::new();
+    for col in self.columns.values() {
       for chunk in col.chunks.iter() {
-        if let Some(mesh_data) = chunk.get_mesh_fast() {
-          let m = (
-            chunk.get_id(),
-            mesh_data
-          );
-          meshes.push(m);
-        }
+        chunks.push((chunk.get_id(), chunk.get_connectivity(), chunk.get_mesh_fast()));
       }
     }
 
-    meshes
+    chunks
   }
 
   pub fn get_chunk_at(&self, chunk_id: &[i32; 3]) -> Option<&Arc<Chunk>> {
-    let cib = &self.chunk_id_bounds;
-    if (cib[0][0]..cib[1][0]).contains(&chunk_id[0]) && //Bounds check. Again.
-      (cib[0][1]..cib[1][1]).contains(&chunk_id[1]) &&
-      (cib[0][2]..cib[1][2]).contains(&chunk_id[2]) 
-    {
-      let rel_pos = [ //Convert to relative position.
-        chunk_id[0] - cib[0][0],
-        chunk_id[1] - cib[0][1],
-        chunk_id[2] - cib[0][2],
-      ];
-
-      Some(&self.columns[(
-        rel_pos[0] * (cib[1][0] - cib[0][0]) + //relative x * x size + relative z
-        rel_pos[2]
-      ) as usize].chunks[rel_pos[1] as usize])
-    } else {
-      None
-    }
+    let column = self.columns.get(&[chunk_id[0], chunk_id[2]])?;
+    let index = chunk_id[1].checked_sub(column.lowest_chunk_y)?;
+    column.chunks.get(usize::try_from(index).ok()?)
   }
 
+
   
   pub fn get_block_at(&self, pos: Vector3<i32>) -> Option<Block> {
-    
-    let div = pos / (CHUNK_SIZE_I32);
-    let neg = pos.map(|v| if v < 0 {-1} else {0});
-    let chunk_id = div+neg;
-
+    let chunk_id = Self::pos_to_chunk_id(pos);
 
     let chunk = self.get_chunk_at(&chunk_id.into())?; //For some reason I've not used vector3s in my terrain data.
 
@@ -203,55 +276,248 @@ impl ChunkedTerrain {
     Some(block)
   }
 
+  ///Sets a block in the terrain and marks the chunk (and any neighbouring chunk that shares the edited block's faces) for revis.
+  ///Returns the block that was replaced, or None if the block is not loaded.
+  pub fn set_block_at(&mut self, pos: Vector3<i32>, block: Block) -> Option<Block> {
+    let chunk_id = Self::pos_to_chunk_id(pos);
+    let inner_pos = pos - (chunk_id * CHUNK_SIZE_I32);
+
+    let chunk = self.get_chunk_at(&chunk_id.into())?;
+    let old_block = chunk.set_block_at(inner_pos.x, inner_pos.y, inner_pos.z, block)?;
+    self.mark_for_revis(chunk_id.into());
+
+    for offset in ADJACENT_OFFSETS { //Update neighbours if the block is on the edge of the chunk.
+      if !Chunk::check_chunk_range(inner_pos + Vector3::from(offset)) {
+        self.mark_for_revis((chunk_id + Vector3::from(offset)).into());
+      }
+    }
+
+    Some(old_block)
+  }
+
+  ///Queues an operation to be applied to a region of blocks. The edit is spread over multiple ticks if it is large.
+  pub fn queue_region_edit(&mut self, region: Region, operation: RegionOperation) {
+    self.edit_queue.push_back(EditJob::new(region, operation));
+  }
+
+  ///Queues setting the blocks in an edit record (for undo, redo and pasting).
+  pub fn queue_block_edit(&mut self, record: EditRecord, origin: EditOrigin) {
+    self.edit_queue.push_back(EditJob::from_record(record, origin));
+  }
+
+  ///Takes the records of edits that have finished since the last call, so they can be added to the edit history.
+  pub fn take_finished_edits(&mut self) -> Vec<(EditOrigin, EditRecord)> {
+    mem::take(&mut self.finished_edits)
+  }
+
+  ///Applies queued region edits to a limited number of chunks so large edits don't stall the tick thread.
+  pub fn tick_edits(&mut self) {
+    let mut budget = EDIT_CHUNKS_PER_TICK;
+    while budget > 0 {
+      let mut job = match self.edit_queue.pop_front() {
+        Some(job) => job,
+        None => break,
+      };
+
+      let mut attempts = job.pending_chunk_count(); //Stops chunks that are still generating from being retried forever in one tick.
+      while budget > 0 && attempts > 0 {
+        attempts -= 1;
+        let chunk_id = job.next_chunk().unwrap();
+        if let Some(chunk) = self.get_chunk_at(&chunk_id) {
+          if job.apply_to_chunk(chunk_id, chunk) {
+            budget -= 1;
+          } else { //Try again once the chunk has generated.
+            job.defer_chunk(chunk_id);
+          }
+        } else if let Some(blocks) = self.saved_chunks.get_mut(&chunk_id) { //Edit the saved copy of modified chunks that have been unloaded.
+          job.apply_to_blocks(chunk_id, blocks);
+          budget -= 1;
+        } //Other chunks that aren't loaded are skipped.
+      }
+
+      if job.is_finished() {
+        self.finish_edit(&job);
+        self.finished_edits.push(job.into_record());
+      } else {
+        self.edit_queue.push_front(job);
+        break;
+      }
+    }
+  }
+
+  ///Marks the chunks changed by an edit for revis, so each chunk only gets its visibility and mesh regenerated once.
+  fn finish_edit(&mut self, job: &EditJob) {
+    for (chunk_id, sides) in job.edited_chunks() {
+      self.mark_for_revis(*chunk_id);
+
+      for (side, offset) in ADJACENT_OFFSETS.iter().enumerate() { //Update neighbours that share an edited face.
+        if sides & (1u8 << side) == 0 {
+          continue;
+        }
+        self.mark_for_revis((Vector3::from(*chunk_id) + Vector3::from(*offset)).into());
+      }
+    }
+  }
+
+  ///Marks a loaded chunk for revis and adds it to the ready set so the task gets sent next tick.
+  fn mark_for_revis(&mut self, chunk_id: [i32; 3]) {
+    if let Some(chunk) = self.get_chunk_at(&chunk_id) {
+      chunk.mark_for_revis();
+      self.ready.insert(chunk_id);
+    }
+  }
+
+  ///Casts a ray through the terrain and returns the first non-air block it hits. The ray stops at unloaded blocks.
+  pub fn raycast(&self, origin: FPVector, direction: Vector3<f32>, max_distance: f32) -> Option<RaycastHit> {
+    cast_ray(origin, direction, max_distance, |pos| {
+      self.get_block_at(pos).map(|block| block != Block::Air)
+    })
+  }
+
   //Call chunk updates.
-  pub fn tick_progress(&self) {
-    for col in self.columns.iter() {
-      for chunk in col.chunks.iter() {
-        let stage = chunk.get_pending_stage();
-        
-        match stage {
-          Some(ChunkStateStage::ChunkGen) => {
+  pub fn tick_progress(&mut self, look_direction: Vector3<f32>) {
+    self.process_events();
+    self.look_direction = look_direction;
+    let (last_chunk_id, last_look) = self.prioritised_view;
+    if last_chunk_id != self.player_last_chunk_id || last_look.dot(look_direction) < REPRIORITISE_COS {
+      self.reprioritise_tasks();
+    }
+
+    //Only chunks in the ready set can have changed state, so idle ticks don't touch any chunks.
+    for chunk_id in mem::take(&mut self.ready) {
+      let chunk = match self.get_chunk_at(&chunk_id) {
+        Some(chunk) => chunk.clone(),
+        None => continue, //Unloaded.
+      };
+
+      match chunk.get_pending_stage() {
+        Some(ChunkStateStage::ChunkGen) => {
+          //If the heightmap isn't ready, the chunk gets put back in the ready set when it is.
+          //It's only generated once a chunk needs it, so columns loaded from saved or cached chunks don't wait for one.
+          let column_heightmap = self.columns[&[chunk_id[0], chunk_id[2]]].height_map.clone();
+          match column_heightmap.get() {
+            Some(height_map) => self.send_task(ChunkTask {
+              chunk,
+              typ: ChunkTaskType::GenTerrain(self.gen.clone(), height_map),
+              events: self.event_sender.clone(),
+            }),
+            None => if column_heightmap.request() {
+              self.send_heightmap_task(column_heightmap);
+            },
+          }
+        },
+        Some(ChunkStateStage::ChunkVisGen) => {
+          let [idx, idy, idz] = chunk_id;
+          let adjacent_chunks = ADJACENT_OFFSETS.map(|[ox, oy, oz]| {
+            self.get_chunk_at(&[idx + ox, idy + oy, idz + oz]).map(|chunk| chunk.clone())
+          });
+          if adjacent_chunks.iter().any(|chunk| { //Check if the chunk is adjacent to chunks that are still generating. If so then wait for them.
+            chunk.as_ref().map_or(false, |chunk| {
+              chunk.get_stage() == ChunkStateStage::ChunkGen
+            })
+          }) {
+            self.deferred.insert(chunk_id);
+          } else { //Then send it to be processed.
             self.send_task(ChunkTask {
-              chunk: chunk.clone(),
-              typ: ChunkTaskType::GenTerrain(self.gen.clone(), col.height_map.clone()),
-            });
-          },
-          Some(ChunkStateStage::ChunkVisGen) => {
-            let [idx, idy, idz] = chunk.get_id();
-            let adjacent_chunks = ADJACENT_OFFSETS.map(|[ox, oy, oz]| {
-              self.get_chunk_at(&[idx + ox, idy + oy, idz + oz]).map(|chunk| chunk.clone())
+              chunk,
+              typ: ChunkTaskType::GenBlockVis(adjacent_chunks),
+              events: self.event_sender.clone(),
             });
-            if !adjacent_chunks.iter().any(|chunk| { //Check if the chunk is adjacent to chunks that are still generating. If so then skip it.
-              chunk.as_ref().map_or(false, |chunk| {
-                chunk.get_stage() == ChunkStateStage::ChunkGen
-              })
-            }) { //Then send it to be processed.
-              self.send_task(ChunkTask {
-                chunk: chunk.clone(),
-                typ: ChunkTaskType::GenBlockVis(adjacent_chunks),
-              });
+          }
+        },
+        Some(ChunkStateStage::MeshGen) => {
+          self.send_task(ChunkTask {
+            chunk,
+            typ: ChunkTaskType::GenVertices,
+            events: self.event_sender.clone(),
+          });
+        },
+        _ => {
+          //Ready, or a task is still running (the worker sends TaskFinished when it's done).
+        },
+      };
+    }
+  }
+
+  ///Handles events sent back by the workers.
+  fn process_events(&mut self) {
+    while let Ok(event) = self.events.try_recv() {
+      match event {
+        ChunkEvent::Generated(chunk_id) => {
+          //Neighbours that generated their visibility without this chunk hid their faces on this side, so they need redoing.
+          //Neighbours that were deferred can go ahead now too.
+          for (side, offset) in ADJACENT_OFFSETS.iter().enumerate() {
+            let neighbour_id = (Vector3::from(chunk_id) + Vector3::from(*offset)).into();
+            if let Some(neighbour) = self.get_chunk_at(&neighbour_id) {
+              neighbour.neighbour_generated(BlockSide::try_from(side as u8).unwrap().opposite());
+              self.deferred.remove(&neighbour_id);
+              self.ready.insert(neighbour_id);
             }
-          },
-          Some(ChunkStateStage::MeshGen) => {
-            self.send_task(ChunkTask {
-              chunk: chunk.clone(),
-              typ: ChunkTaskType::GenVertices,
-            });
-          },
-          _ => {
-            //Do nothing for now.
-          },
-        };
+          }
+        },
+        ChunkEvent::TaskFinished(chunk_id) => {
+          self.ready.insert(chunk_id);
+        },
+        ChunkEvent::HeightmapReady(column_pos) => {
+          if let Some(column) = self.columns.get(&column_pos) {
+            self.ready.extend(column.chunks.iter().map(|chunk| chunk.get_id()));
+          }
+        },
       }
     }
   }
 
   fn send_task(&self, task: ChunkTask) {
     if task.chunk.assign_if_waiting() {
-      self.worker_pool_sender.send(task).unwrap();
+      let priority = Self::task_priority(task.chunk.get_id(), self.player_last_chunk_id, self.look_direction);
+      self.task_queue.push(task, priority);
     }
   }
 
+  fn send_heightmap_task(&self, column: Arc<ColumnHeightmap>) {
+    let task = WorkerTask::Heightmap(HeightmapTask {
+      column,
+      gen: self.gen.clone(),
+      events: self.event_sender.clone()
+    });
+    let priority = Self::worker_task_priority(&task, self.player_last_chunk_id, self.look_direction);
+    self.task_queue.push(task, priority);
+  }
+
+  ///Updates the priorities of queued tasks after the player moves or turns. Tasks for chunks that have been unloaded are dropped.
+  fn reprioritise_tasks(&mut self) {
+    self.prioritised_view = (self.player_last_chunk_id, self.look_direction);
+    let (player_chunk_id, look_direction) = self.prioritised_view;
+    self.task_queue.reprioritise(|task| {
+      if task.is_cancelled() {
+        None
+      } else {
+        Some(Self::worker_task_priority(task, player_chunk_id, look_direction))
+      }
+    });
+  }
+
+  ///Heightmap and LOD tasks get the priority of the chunk in their (middle) column that is level with the player, because it's the closest one that needs it.
+  fn worker_task_priority(task: &WorkerTask, player_chunk_id: Vector3<i32>, look_direction: Vector3<f32>) -> u32 {
+    let chunk_id = match task {
+      WorkerTask::Chunk(task) => task.chunk.get_id(),
+      WorkerTask::Heightmap(_) | WorkerTask::Lod(_) => {
+        let [x, z] = task.column();
+        [x, player_chunk_id.y, z]
+      },
+    };
+    Self::task_priority(chunk_id, player_chunk_id, look_direction)
+  }
+
+  ///Lower numbers get processed first. Closer chunks go first, and chunks that aren't in view are treated as being further away.
+  fn task_priority(chunk_id: [i32; 3], player_chunk_id: Vector3<i32>, look_direction: Vector3<f32>) -> u32 {
+    let offset = Vector3::from(chunk_id) - player_chunk_id;
+    let dist_sq = offset.x.pow(2) + offset.y.pow(2) + offset.z.pow(2);
+    let in_view = dist_sq <= 3 || offset.map(|v| v as f32).normalize().dot(look_direction) > VIEW_COS; //Chunks touching the player's chunk always count as in view.
+
+    (if in_view {dist_sq} else {dist_sq * OUT_OF_VIEW_PENALTY}) as u32
+  }
+
 
   /**
     Tests the hitbox from its faces. Blocks that are completely inside the hitbox will not be checked.
@@ -458,75 +724,70 @@ impl ChunkedTerrain {
   }
 
 
-  fn reuse_column(&self, column: &mut ChunkColumn, new_bounds: [[i32; 3]; 2], column_pos: [i32; 2], regen: [ChunkRegenCoord; 3]) {
-    let [ncx, ncz] = column_pos;
-    //Variable names for simplicity
-    let old_start = self.chunk_id_bounds[0][1];
-    let old_end = self.chunk_id_bounds[1][1];
-    let new_start = new_bounds[0][1];
-    let new_end = new_bounds[1][1];
-
-    let y_range_size = new_end - new_start;
-    let old_chunk_list = mem::replace(&mut column.chunks, Vec::<Arc<Chunk>>::with_capacity(y_range_size as usize));
-    
-    if new_start < old_end || new_end > old_start { //If there is an overlap.
-      //Varaible names correspond to arraystuff.png
-      let red = (new_start - old_start).max(0);
-      let green = old_start.max(new_start);
-      let blue = old_end.min(new_end);
-
-      let mut useful_old_chunks = old_chunk_list.into_iter();
-      for _ in 0..red {
-        let chunk = useful_old_chunks.next().unwrap();
-        self.chunk_gc.send(chunk).unwrap();
-      }
-
-      for ncy in new_start..green {
-        let new_chunk = make_new_chunk([ncx, ncy, ncz]);
-        column.chunks.push(new_chunk)
-      }
-
-      for ncy in green..blue {
-        let reused_chunk = useful_old_chunks.next();
-        match reused_chunk {
-          Some(reused_chunk) => {
-            let needs_regen = [ncx, ncy, ncz].iter().zip(regen.iter()).any(|(val, regen_coord)| {
-              match regen_coord { //Check if the chunk needs regenerating.
-                ChunkRegenCoord::None => false,
-                ChunkRegenCoord::One(new_val) => val == new_val,
-                ChunkRegenCoord::Both(lo, hi) => val == lo || val == hi,
-              }
-            });
-            if needs_regen {
-              reused_chunk.mark_for_revis();
-            }
-            column.chunks.push(reused_chunk);
-          },
-          None => {
-            panic!("Reused chunk did not exist.")
-          },
-        }
+  ///Changes the chunks loaded in a column to a new range of chunk y values. Chunks in both ranges are kept.
+  fn resize_column(&mut self, column: &mut ChunkColumn, column_pos: [i32; 2], range: RangeInclusive<i32>) {
+    let old_lowest = column.lowest_chunk_y;
+    let mut kept_chunks: Vec<Option<Arc<Chunk>>> = range.clone().map(|_| None).collect();
+    for (cy, chunk) in (old_lowest..).zip(mem::take(&mut column.chunks)) {
+      if range.contains(&cy) {
+        kept_chunks[(cy - range.start()) as usize] = Some(chunk);
+      } else {
+        self.unload_chunk(chunk);
       }
+    }
 
-      for ncy in blue..new_end {
-        let new_chunk = make_new_chunk([ncx, ncy, ncz]);
-        column.chunks.push(new_chunk)
-      }
+    column.lowest_chunk_y = *range.start();
+    for (cy, kept_chunk) in range.zip(kept_chunks) {
+      let chunk = match kept_chunk {
+        Some(chunk) => chunk,
+        None => self.load_chunk([column_pos[0], cy, column_pos[1]]),
+      };
+      column.chunks.push(chunk);
+    }
+  }
 
-      useful_old_chunks.for_each(|remaining_chunk| { //Send remaining chunks to gc.
-        self.chunk_gc.send(remaining_chunk).unwrap();
-      });
+  ///Creates a chunk, using the saved blocks if the chunk was modified before it was unloaded, or the cached ones if it was unloaded recently.
+  fn load_chunk(&mut self, chunk_id: [i32; 3]) -> Arc<Chunk> {
+    self.ready.insert(chunk_id);
+    let chunk = if let Some(blocks) = self.saved_chunks.remove(&chunk_id) {
+      Chunk::from_blocks(chunk_id, blocks)
+    } else if let Some(cached) = self.chunk_cache.take(chunk_id) {
+      Chunk::from_cached(chunk_id, cached)
+    } else {
+      return make_new_chunk(chunk_id);
+    };
+    let _ = self.event_sender.send(ChunkEvent::Generated(chunk_id)); //Already has blocks so the neighbours can use them straight away.
+    Arc::new(chunk)
+  }
 
-    } else { //Completely new chunks.
-      for ncy in new_start..new_end {
-        let new_chunk = make_new_chunk([ncx, ncy, ncz]);
-        column.chunks.push(new_chunk)
+  ///Cancels any queued work for the chunk, saves the blocks of modified chunks (or caches unmodified ones) and sends the chunk to gc so deleting it doesn't block this thread.
+  fn unload_chunk(&mut self, chunk: Arc<Chunk>) {
+    chunk.cancel();
+    if let Some(blocks) = chunk.clone_blocks() {
+      if chunk.is_modified() {
+        self.saved_chunks.insert(chunk.get_id(), blocks);
+      } else {
+        self.chunk_cache.insert(chunk.get_id(), CachedChunk { blocks, mesh: chunk.take_mesh() });
       }
+    }
+    self.chunk_gc.send(chunk).unwrap();
+  }
 
-      for chunk in old_chunk_list {
-        self.chunk_gc.send(chunk).unwrap();
-      }
+  ///Counters and timings for each stage of the chunk pipeline, including how many loaded chunks are in each stage.
+  pub fn get_pipeline_stats(&self) -> PipelineSnapshot {
+    let mut chunks_per_stage = [0; ChunkStateStage::ALL.len()];
+    for chunk in self.columns.values().flat_map(|column| column.chunks.iter()) {
+      chunks_per_stage[chunk.get_stage() as usize] += 1;
     }
+    PipelineSnapshot {
+      tasks: self.task_queue.get_stats().snapshot(),
+      chunks_per_stage
+    }
+  }
+
+  ///How many chunks are in the unloaded chunk cache and how many bytes they use.
+  pub fn get_cache_info(&self) -> (usize, usize) {
+    (self.chunk_cache.len(), self.chunk_cache.memory_used())
   }
 
   pub fn pos_to_chunk_id(pos: Vector3<i32>) -> Vector3<i32> {
@@ -539,35 +800,88 @@ fn make_new_chunk(chunk_id: [i32; 3]) -> Arc<Chunk> {
   Arc::new(new_chunk)
 }
 
+///Gets the y of the highest solid block at a block x and z (ignoring caves). LOD tiles use this too so they line up with the real terrain.
+pub fn surface_height(gen: &Perlin, x: i32, z: i32) -> i32 {
+  let minor_hm = gen.get([
+    (x as f64 + 18284.0) / 30.0,
+    (z as f64 - 54761.0) / 30.0
+  ]) * 5.0 + 5.0;
+
+  let major_hm = gen.get([
+    (x as f64 - 4892.0) / 300.0,
+    (z as f64 + 645456.0) / 300.0
+  ]) * 50.0 + 50.0;
+
+  (major_hm + minor_hm + 5.0) as i32
+}
+
 /// A column of chunks. Includes the heightmap for the chunk.
 struct ChunkColumn {
-  pub chunks: Vec<Arc<Chunk>>,
-  pub height_map: Arc<SurfaceHeightmap>
+  pub chunks: Vec<Arc<Chunk>>, //Ordered by chunk y, starting from lowest_chunk_y.
+  pub lowest_chunk_y: i32,
+  pub height_map: Arc<ColumnHeightmap>
+}
+
+///The heightmap of a column. It starts empty and gets generated by a worker (see HeightmapTask).
+pub struct ColumnHeightmap {
+  column_pos: [i32; 2], //Chunk x and z.
+  height_map: OnceLock<Arc<SurfaceHeightmap>>,
+  requested: AtomicBool, //Set when the task to generate it is sent.
+  cancelled: AtomicBool //Set when the column is unloaded.
 }
 
 impl ChunkColumn {
-  fn new(gen: &Perlin, chunk_xz: [i32; 2]) -> Self {
-    let noise_coords = chunk_xz.map(|val| (val*CHUNK_SIZE_I32) as f64);
+  fn new(chunk_xz: [i32; 2], lowest_chunk_y: i32) -> Self {
+    Self {
+      chunks: Vec::new(),
+      lowest_chunk_y,
+      height_map: Arc::new(ColumnHeightmap::new(chunk_xz))
+    }
+  }
+}
+
+impl ColumnHeightmap {
+  pub fn new(column_pos: [i32; 2]) -> Self {
+    Self {
+      column_pos,
+      height_map: OnceLock::new(),
+      requested: AtomicBool::new(false),
+      cancelled: AtomicBool::new(false)
+    }
+  }
+
+  ///Samples the surface height of every block column. Returns false if it had already been generated.
+  pub fn generate(&self, gen: &Perlin) -> bool {
+    let block_xz = self.column_pos.map(|val| val*CHUNK_SIZE_I32);
     
     let mut height_map: SurfaceHeightmap = [0i32; HEIGHTMAP_SIZE];
     for ((x,z), hm) in iproduct!(CHUNK_RANGE, CHUNK_RANGE).zip(height_map.iter_mut()) {
-      let minor_hm = gen.get([
-        (noise_coords[0] + x as f64 + 18284.0) / 30.0,
-        (noise_coords[1] + z as f64 - 54761.0) / 30.0
-      ]) * 5.0 + 5.0;
+      *hm = surface_height(gen, block_xz[0] + x as i32, block_xz[1] + z as i32);
+    }
 
-      let major_hm = gen.get([
-        (noise_coords[0] + x as f64 - 4892.0) / 300.0,
-        (noise_coords[1] + z as f64 + 645456.0) / 300.0
-      ]) * 50.0 + 50.0;
+    self.height_map.set(Arc::new(height_map)).is_ok()
+  }
 
-      *hm = (major_hm + minor_hm + 5.0) as i32;
-    }
+  ///Gets the heightmap if it has been generated.
+  pub fn get(&self) -> Option<Arc<SurfaceHeightmap>> {
+    self.height_map.get().cloned()
+  }
 
-    Self {
-      chunks: Vec::new(),
-      height_map: Arc::new(height_map)
-    }
+  pub fn get_column_pos(&self) -> [i32; 2] {
+    self.column_pos
+  }
+
+  ///Returns true the first time it's called, so the heightmap only gets one task.
+  pub fn request(&self) -> bool {
+    !self.requested.swap(true, Ordering::Relaxed)
+  }
+
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed)
   }
 }
 
@@ -582,13 +896,6 @@ impl BlockArea {
   }
 }
 
-#[derive(Clone, Copy, Debug)]
-enum ChunkRegenCoord {
-  None,
-  One(i32),
-  Both(i32, i32)
-}
-
 
 //Create an iterator of ints between 2 float points.
 fn get_layers_between(a: Fixed64, b: Fixed64, inclusive: bool) -> Option<RangeStepInclusive<i32>> {
@@ -609,15 +916,187 @@ fn get_layers_between(a: Fixed64, b: Fixed64, inclusive: bool) -> Option<RangeSt
   }
 }
 
+///Makes a terrain around the origin for tests, with a task queue that no workers take tasks from. Unloaded chunks get sent to the receiver.
+#[cfg(test)]
+pub(super) fn test_terrain_with(settings: TerrainSettings) -> (ChunkedTerrain, Receiver<Arc<Chunk>>) {
+  let (gc_tx, gc_rx) = channel();
+  (ChunkedTerrain::new(FPVector::from([0, 0, 0]), settings, Default::default(), Arc::new(ChunkTaskQueue::new(1)), gc_tx), gc_rx)
+}
+
+///Like test_terrain_with, without LOD tiles or a memory budget.
+#[cfg(test)]
+pub(super) fn test_terrain(load_area: LoadArea, chunk_cache: ChunkCache) -> (ChunkedTerrain, Receiver<Arc<Chunk>>) {
+  test_terrain_with(TerrainSettings { load_area, lod_levels: 0, chunk_cache, memory_budget: MemoryBudget::default() })
+}
 
 //Tests to make sure get_layers_between(..) works properly.
 #[cfg(test)]
 mod tests {
+  use std::{collections::VecDeque, mem::size_of, sync::{Arc, mpsc::channel}};
+
+  use cgmath::Vector3;
   use itertools::Itertools;
 
-use crate::util::Fixed64;
+use crate::{util::{Fixed64, FPVector}, world::{chunk::Chunk, chunk_worker_pool::WorkerTask, block::Block, chunk_cache::{CachedChunk, ChunkCache}, load_area::{LoadArea, LoadShape}, memory_budget::MemoryBudget}};
+
+  use super::{get_layers_between, test_terrain, test_terrain_with, TerrainSettings, CHUNK_LENGTH, CHUNK_SIZE_I32};
+
+  ///Makes a cache with blocks for every chunk in the area around the origin, so the chunks start with blocks.
+  fn cache_for(area: LoadArea) -> ChunkCache {
+    let mut cache = ChunkCache::new(usize::MAX);
+    for ([x, z], range) in area.columns() {
+      for y in range {
+        cache.insert([x, y, z], CachedChunk { blocks: vec![Block::Air; CHUNK_LENGTH], mesh: None });
+      }
+    }
+    cache
+  }
+
+  #[test]
+  fn test_idle_tick_does_nothing() {
+    let area = LoadArea { shape: LoadShape::Cylinder, radius: 1, vertical_radius: 1 };
+    let (mut terrain, _gc_rx) = test_terrain(area, ChunkCache::new(0));
+    let task_queue = terrain.task_queue.clone();
+    let look = Vector3::new(0.0, 0.0, 1.0);
+
+    terrain.tick_progress(look);
+    assert_eq!(task_queue.len(), terrain.columns.len()); //Only the heightmaps can be generated to start with.
+    assert!(terrain.ready.is_empty());
+
+    terrain.tick_progress(look); //Nothing has finished so nothing should be sent.
+    assert_eq!(task_queue.len(), terrain.columns.len());
+    assert!(terrain.ready.is_empty());
+  }
+
+  #[test]
+  fn test_chunks_wait_for_heightmap() {
+    let area = LoadArea { shape: LoadShape::Cylinder, radius: 1, vertical_radius: 1 };
+    let (mut terrain, _gc_rx) = test_terrain(area, ChunkCache::new(0));
+    let task_queue = terrain.task_queue.clone();
+    let look = Vector3::new(0.0, 0.0, 1.0);
+    terrain.tick_progress(look);
+
+    let mut tasks = VecDeque::new();
+    task_queue.pop_batch(usize::MAX, &mut tasks);
+    for task in tasks {
+      match task {
+        WorkerTask::Heightmap(task) => task.run(),
+        WorkerTask::Chunk(_) => panic!("Chunk task sent before its heightmap was ready"),
+        WorkerTask::Lod(_) => panic!("LOD task sent with LOD turned off"),
+      }
+    }
+
+    terrain.tick_progress(look); //Every chunk gets a gen task now.
+    assert_eq!(task_queue.len(), area.chunk_count());
+  }
+
+  #[test]
+  fn test_cached_columns_skip_heightmap() {
+    let area = LoadArea { shape: LoadShape::Cylinder, radius: 1, vertical_radius: 1 };
+    let (mut terrain, _gc_rx) = test_terrain(area, cache_for(area));
+    let task_queue = terrain.task_queue.clone();
+    terrain.tick_progress(Vector3::new(0.0, 0.0, 1.0));
+
+    let mut tasks = VecDeque::new();
+    task_queue.pop_batch(usize::MAX, &mut tasks);
+    assert_eq!(tasks.len(), area.chunk_count()); //Vis gen for every chunk.
+    assert!(tasks.iter().all(|task| !matches!(task, WorkerTask::Heightmap(_))));
+  }
+
+  #[test]
+  fn test_moving_drops_unloaded_tasks() {
+    let area = LoadArea { shape: LoadShape::Cylinder, radius: 2, vertical_radius: 1 };
+    //Chunks near the start are cached so they get vis gen tasks, and the ones further away need heightmaps.
+    let cache = cache_for(LoadArea { radius: 4, ..area });
+    let (mut terrain, gc_rx) = test_terrain(area, cache);
+    let task_queue = terrain.task_queue.clone();
+    let look = Vector3::new(1.0, 0.0, 0.0);
+    terrain.tick_progress(look);
+
+    //Fly two chunks per tick without any workers running, so nothing gets done.
+    for step in 1..=5 {
+      assert!(terrain.update_player_position(&FPVector::from([step * 2 * CHUNK_SIZE_I32, 0, 0])));
+      terrain.tick_progress(look);
+      assert!(task_queue.len() <= area.chunk_count());
+    }
+    assert!(gc_rx.try_iter().count() > 0);
+
+    let mut tasks = VecDeque::new();
+    task_queue.pop_batch(usize::MAX, &mut tasks);
+    assert!(!tasks.is_empty());
+    for task in tasks {
+      assert!(!task.is_cancelled());
+      assert!(terrain.columns.contains_key(&task.column()));
+    }
+  }
+
+  #[test]
+  fn test_shrink_load_area() {
+    let (big, small) = (LoadArea { shape: LoadShape::Cylinder, radius: 3, vertical_radius: 1 }, LoadArea { shape: LoadShape::Cylinder, radius: 1, vertical_radius: 1 });
+    let (mut terrain, gc_rx) = test_terrain(big, ChunkCache::new(0));
+    let inner_chunks: Vec<Arc<Chunk>> = small.columns().flat_map(|(column_pos, _)| terrain.columns[&column_pos].chunks.clone()).collect();
+
+    terrain.set_load_area(small);
+    assert!(terrain.update_player_position(&FPVector::from([0, 0, 0])));
+    assert_eq!(terrain.columns.len(), small.columns().count());
+    for chunk in inner_chunks { //The inner chunks are kept rather than reloaded.
+      assert!(Arc::ptr_eq(&chunk, terrain.get_chunk_at(&chunk.get_id()).unwrap()));
+    }
+    let unloaded: Vec<Arc<Chunk>> = gc_rx.try_iter().collect();
+    assert_eq!(unloaded.len(), big.chunk_count() - small.chunk_count());
+    assert!(unloaded.iter().all(|chunk| chunk.is_cancelled() && terrain.get_chunk_at(&chunk.get_id()).is_none()));
+  }
+
+  #[test]
+  fn test_grow_load_area() {
+    let (small, big) = (LoadArea { shape: LoadShape::Cylinder, radius: 1, vertical_radius: 1 }, LoadArea { shape: LoadShape::Cylinder, radius: 3, vertical_radius: 2 });
+    let (mut terrain, gc_rx) = test_terrain(small, ChunkCache::new(0));
+    let old_chunks: Vec<Arc<Chunk>> = terrain.columns.values().flat_map(|column| column.chunks.clone()).collect();
+
+    terrain.set_load_area(big);
+    assert!(terrain.update_player_position(&FPVector::from([0, 0, 0])));
+    assert_eq!(terrain.columns.values().map(|column| column.chunks.len()).sum::<usize>(), big.chunk_count());
+    for chunk in old_chunks { //Loaded columns are extended, not rebuilt.
+      assert!(Arc::ptr_eq(&chunk, terrain.get_chunk_at(&chunk.get_id()).unwrap()));
+    }
+    assert_eq!(gc_rx.try_iter().count(), 0);
+  }
 
-  use super::get_layers_between;
+  #[test]
+  fn test_over_budget_unloads_far_chunks() {
+    let area = LoadArea { shape: LoadShape::Cylinder, radius: 3, vertical_radius: 0 };
+    let cache = cache_for(area); //Every chunk starts with blocks so they all use memory straight away.
+    let budget = MemoryBudget { blocks: LoadArea { radius: 1, ..area }.chunk_count() * CHUNK_LENGTH * size_of::<Block>(), meshes: 0 };
+    let (mut terrain, _gc_rx) = test_terrain_with(TerrainSettings { load_area: area, lod_levels: 0, chunk_cache: cache, memory_budget: budget });
+    assert_eq!(terrain.columns.len(), area.columns().count());
+
+    terrain.tick_memory_budget();
+    assert_eq!(terrain.get_effective_load_area().radius, 1);
+    assert_eq!(terrain.get_load_area().radius, 3);
+    terrain.update_player_position(&FPVector::from([0, 0, 0]));
+    assert_eq!(terrain.columns.len(), 5);
+    assert!(terrain.columns.keys().all(|[x, z]| x.abs() + z.abs() <= 1)); //Only the closest columns are kept.
+  }
+
+  #[test]
+  fn test_lod_tiles_sent_for_upload() {
+    let area = LoadArea { shape: LoadShape::Cylinder, radius: 1, vertical_radius: 1 };
+    let (mesh_tx, mesh_rx) = channel();
+    let (terrain, _gc_rx) = test_terrain_with(TerrainSettings { load_area: area, lod_levels: 2, chunk_cache: ChunkCache::new(0), memory_budget: MemoryBudget::default() });
+    let task_queue = terrain.task_queue.clone();
+    let queued = terrain.lod_tiles.len();
+    assert!(queued > 0);
+    assert!(terrain.get_lod_meshes().is_empty()); //Nothing is generated on this thread.
+
+    let mut tasks = VecDeque::new();
+    task_queue.pop_batch(usize::MAX, &mut tasks);
+    for task in tasks {
+      if let WorkerTask::Lod(task) = task {
+        task.run(&mesh_tx);
+      }
+    }
+    assert_eq!(mesh_rx.try_iter().count(), queued);
+  }
 
   fn test_range(a: f32, b: f32, incl: bool, expt: Option<Vec<i32>>) {
     let lb = get_layers_between(Fixed64::from_num(a), Fixed64::from_num(b), incl);
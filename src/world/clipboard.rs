@@ -0,0 +1,163 @@
+use cgmath::Vector3;
+use itertools::iproduct;
+
+use super::{block::Block, chunk::Chunk, chunkedterrain::{ChunkedTerrain, CHUNK_SIZE_I32}, edit::{EditRecord, Region}};
+
+pub const MAX_CLIPBOARD_BLOCKS: u64 = 4 * 1024 * 1024; //The biggest region that can be copied, so a huge selection can't stall the tick or fill the undo history.
+
+///Blocks copied from a region of the world.
+pub struct Clipboard {
+  size: Vector3<i32>,
+  blocks: Vec<Block> //In x, then y, then z order (same as chunks).
+}
+
+///How the clipboard is turned when it is pasted. Mirroring happens before rotating.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PasteTransform {
+  pub rotation: u8, //Quarter turns around the Y axis.
+  pub mirror_x: bool, //Flips the X coordinates.
+  pub mirror_z: bool, //Flips the Z coordinates.
+}
+
+impl PasteTransform {
+  pub fn rotate(&mut self) {
+    self.rotation = (self.rotation + 1) % 4;
+  }
+
+  ///Gets the size of a box after it has been transformed.
+  pub fn transform_size(&self, size: Vector3<i32>) -> Vector3<i32> {
+    if self.rotation % 2 == 1 {
+      Vector3::new(size.z, size.y, size.x)
+    } else {
+      size
+    }
+  }
+
+  ///Moves a position inside a box of the given size to where it ends up after the transform. Both positions are relative to the lowest corner.
+  pub fn transform_pos(&self, pos: Vector3<i32>, size: Vector3<i32>) -> Vector3<i32> {
+    let mut pos = pos;
+    if self.mirror_x {
+      pos.x = size.x - 1 - pos.x;
+    }
+    if self.mirror_z {
+      pos.z = size.z - 1 - pos.z;
+    }
+
+    let mut depth = size.z;
+    for _ in 0..self.rotation {
+      //Turn (x, z) to (depth - 1 - z, x) so the box stays in positive coordinates. The width and depth swap each turn.
+      pos = Vector3::new(depth - 1 - pos.z, pos.y, pos.x);
+      depth = if depth == size.z {size.x} else {size.z};
+    }
+    pos
+  }
+}
+
+impl Clipboard {
+  ///Copies the blocks in a region, one chunk at a time. Returns None if any of the blocks are not loaded.
+  ///The region should have no more than MAX_CLIPBOARD_BLOCKS blocks.
+  pub fn copy(terrain: &ChunkedTerrain, region: Region) -> Option<Self> {
+    let size = region.size();
+    let mut blocks = vec![Block::Air; region.block_count() as usize];
+
+    for chunk_id in region.chunk_ids() {
+      let area = region.chunk_intersection(chunk_id)?;
+      let chunk_pos = Vector3::from(chunk_id) * CHUNK_SIZE_I32;
+      terrain.get_chunk_at(&chunk_id)?.read_blocks(|chunk_blocks| {
+        for (x, y, z) in iproduct!(area.lo.x..=area.hi.x, area.lo.y..=area.hi.y, area.lo.z..=area.hi.z) {
+          let (rel_pos, clipboard_pos) = (Vector3::new(x, y, z) - chunk_pos, Vector3::new(x, y, z) - region.lo);
+          let index = (clipboard_pos.x * size.y * size.z + clipboard_pos.y * size.z + clipboard_pos.z) as usize;
+          blocks[index] = chunk_blocks[Chunk::rel_pos_to_index(rel_pos.x, rel_pos.y, rel_pos.z).unwrap()];
+        }
+      })?;
+    }
+
+    Some(Self {
+      size,
+      blocks
+    })
+  }
+
+  pub fn get_size(&self) -> Vector3<i32> {
+    self.size
+  }
+
+  ///Gets the region the clipboard would cover if it was pasted with its lowest corner at origin.
+  pub fn paste_region(&self, origin: Vector3<i32>, transform: PasteTransform) -> Region {
+    let size = transform.transform_size(self.size);
+    Region {
+      lo: origin,
+      hi: origin + size - Vector3::new(1, 1, 1)
+    }
+  }
+
+  ///Gets the blocks to set to paste the clipboard with its lowest corner at origin.
+  pub fn paste_record(&self, origin: Vector3<i32>, transform: PasteTransform) -> EditRecord {
+    let positions = iproduct!(0..self.size.x, 0..self.size.y, 0..self.size.z);
+    EditRecord::from_blocks(positions.zip(self.blocks.iter()).map(|((x, y, z), block)| {
+      (origin + transform.transform_pos(Vector3::new(x, y, z), self.size), *block)
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use cgmath::Vector3;
+  use itertools::iproduct;
+
+  use crate::world::{block::Block, chunk_cache::{CachedChunk, ChunkCache}, chunkedterrain::{test_terrain, CHUNK_LENGTH, CHUNK_SIZE_I32}, edit::Region, load_area::{LoadArea, LoadShape}};
+
+  use super::{Clipboard, PasteTransform};
+
+  #[test]
+  fn test_copy_across_chunks() {
+    let area = LoadArea { shape: LoadShape::Cylinder, radius: 1, vertical_radius: 1 };
+    let mut cache = ChunkCache::new(usize::MAX);
+    for ([x, z], range) in area.columns() {
+      for y in range {
+        let blocks = (0..CHUNK_LENGTH).map(|i| Block::ALL[(i + (x + y * 3 + z * 5).unsigned_abs() as usize) % Block::ALL.len()]).collect();
+        cache.insert([x, y, z], CachedChunk { blocks, mesh: None });
+      }
+    }
+    let (terrain, _gc_rx) = test_terrain(area, cache);
+
+    let region = Region { lo: Vector3::new(-3, -3, 1), hi: Vector3::new(2, 4, 5) };
+    let clipboard = Clipboard::copy(&terrain, region).unwrap();
+    let expected: Vec<Block> = iproduct!(-3..=2, -3..=4, 1..=5).map(|(x, y, z)| terrain.get_block_at(Vector3::new(x, y, z)).unwrap()).collect();
+    assert_eq!(clipboard.blocks, expected);
+
+    let unloaded = Region { lo: Vector3::new(0, 0, 0), hi: Vector3::new(CHUNK_SIZE_I32 * 2, 0, 0) };
+    assert!(Clipboard::copy(&terrain, unloaded).is_none());
+  }
+
+  #[test]
+  fn test_rotation() {
+    let size = Vector3::new(3, 1, 2);
+    let mut transform = PasteTransform::default();
+    transform.rotate();
+    assert_eq!(transform.transform_size(size), Vector3::new(2, 1, 3));
+    assert_eq!(transform.transform_pos(Vector3::new(0, 0, 0), size), Vector3::new(1, 0, 0));
+    assert_eq!(transform.transform_pos(Vector3::new(2, 0, 1), size), Vector3::new(0, 0, 2));
+
+    for _ in 0..3 { //Four turns gets back to the start.
+      transform.rotate();
+    }
+    assert_eq!(transform.transform_pos(Vector3::new(2, 0, 1), size), Vector3::new(2, 0, 1));
+  }
+
+  #[test]
+  fn test_transform_stays_in_box() {
+    let size = Vector3::new(4, 2, 3);
+    for (rotation, mirror_x, mirror_z) in iproduct!(0..4u8, [false, true], [false, true]) {
+      let transform = PasteTransform { rotation, mirror_x, mirror_z };
+      let new_size = transform.transform_size(size);
+      let mut positions: Vec<[i32; 3]> = iproduct!(0..size.x, 0..size.y, 0..size.z)
+        .map(|(x, y, z)| transform.transform_pos(Vector3::new(x, y, z), size).into())
+        .collect();
+      positions.sort();
+      positions.dedup();
+      assert_eq!(positions.len(), (size.x * size.y * size.z) as usize); //No two blocks end up in the same place.
+      assert!(positions.iter().all(|p| (0..3).all(|i| 0 <= p[i] && p[i] < new_size[i])));
+    }
+  }
+}
@@ -1,11 +1,19 @@
-use std::{collections::HashMap};
-use winit::event::VirtualKeyCode;
+use std::{collections::{HashMap, HashSet}};
+use winit::event::{VirtualKeyCode, MouseButton};
 
 //TODO swap hashmaps with EnumMap(s).
 //TODO make bindings more efficient so I don't have to use searches.
 pub struct Controller {
-  keys: HashMap<VirtualKeyCode, bool>,
-  bindings: HashMap<Control, Vec<VirtualKeyCode>>,
+  keys: HashMap<InputKey, bool>,
+  bindings: HashMap<Control, Vec<InputKey>>,
+  pressed: HashSet<InputKey>, //Keys that have been pressed since they were last taken with take_action.
+}
+
+///A key or button that can be bound to a control.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputKey {
+  Keyboard(VirtualKeyCode),
+  Mouse(MouseButton)
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
@@ -16,7 +24,20 @@ pub enum Control {
   Right,
   Up,
   Down,
-  UnlockMouse
+  UnlockMouse,
+  BreakBlock,
+  PlaceBlock,
+  PickBlock,
+  SelectCorner1,
+  SelectCorner2,
+  Undo,
+  Redo,
+  Copy,
+  Cut,
+  Paste,
+  RotatePaste,
+  MirrorPasteX,
+  MirrorPasteZ
 }
 
 impl Controller {
@@ -24,20 +45,42 @@ impl Controller {
     Self {
       keys: HashMap::new(),
       bindings: HashMap::new(),
+      pressed: HashSet::new(),
     }
   }
 
   pub fn set_key(&mut self, key: VirtualKeyCode, state: bool) {
-    self.keys.insert(key, state);
+    self.set_input(InputKey::Keyboard(key), state);
+  }
+
+  pub fn set_mouse_button(&mut self, button: MouseButton, state: bool) {
+    self.set_input(InputKey::Mouse(button), state);
+  }
+
+  fn set_input(&mut self, input: InputKey, state: bool) {
+    let was_down = self.keys.insert(input, state).unwrap_or(false);
+    if state && !was_down { //Ignore key repeats.
+      self.pressed.insert(input);
+    }
   }
 
   pub fn set_bindings(&mut self, binds: &[(VirtualKeyCode, Control)]){
     for (key, action) in binds {
-      match self.bindings.get_mut(action) {
-        Some(action_list) => action_list.push(*key),
-        None => {
-          self.bindings.insert(*action, vec![*key]);
-        }
+      self.bind(InputKey::Keyboard(*key), *action);
+    }
+  }
+
+  pub fn set_mouse_bindings(&mut self, binds: &[(MouseButton, Control)]){
+    for (button, action) in binds {
+      self.bind(InputKey::Mouse(*button), *action);
+    }
+  }
+
+  fn bind(&mut self, input: InputKey, action: Control) {
+    match self.bindings.get_mut(&action) {
+      Some(action_list) => action_list.push(input),
+      None => {
+        self.bindings.insert(action, vec![input]);
       }
     }
   }
@@ -54,6 +97,14 @@ impl Controller {
     }
   }
 
+  ///Returns true (once) if the action has been pressed since the last time this was called. Used for one-off actions like placing blocks.
+  pub fn take_action(&mut self, action: Control) -> bool {
+    match self.bindings.get(&action) {
+      Some(binds) => binds.iter().filter(|input| self.pressed.remove(input)).count() > 0, //Removes every bound input, not just the first one pressed.
+      None => false,
+    }
+  }
+
   pub fn get_action_value<T>(&self, a: (Control, T), b: (Control, T), default: T) -> T {
     let a_pressed = self.get_action(a.0);
     let b_pressed = self.get_action(b.0);
@@ -0,0 +1,448 @@
+use std::collections::{HashMap, VecDeque};
+
+use cgmath::Vector3;
+use itertools::iproduct;
+
+use super::{block::{Block, BlockSide}, chunk::Chunk, chunkedterrain::{ChunkedTerrain, CHUNK_SIZE_I32}};
+
+///An axis-aligned box of blocks. Both corners are inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+  pub lo: Vector3<i32>,
+  pub hi: Vector3<i32>
+}
+
+///Operations that can be applied to every block in a region (like WorldEdit).
+#[derive(Clone, Copy, Debug)]
+pub enum RegionOperation {
+  Fill(Block),
+  Replace(Block, Block), //Replaces the first block with the second.
+  Hollow(Block), //Makes the outer shell of the region out of the block and fills the inside with air.
+  Walls(Block), //Only the four vertical sides of the region.
+  Sphere(Block), //A sphere (or ellipsoid) that fits inside the region.
+}
+
+///Blocks for each chunk as (index in chunk, block) pairs.
+pub type ChunkBlockChanges = HashMap<[i32; 3], Vec<(u16, Block)>>;
+
+///Blocks to put into the world. Edits keep a record of the blocks they replaced so they can be undone (and undos keep one so they can be redone).
+#[derive(Default)]
+pub struct EditRecord {
+  blocks: ChunkBlockChanges
+}
+
+///Where an edit came from, so the history knows what to do with its record when it is finished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditOrigin {
+  Edit,
+  Undo,
+  Redo
+}
+
+///Stacks of edit records that can be undone and redone. Old records get thrown away once the memory cap is reached.
+pub struct EditHistory {
+  undo_stack: VecDeque<EditRecord>,
+  redo_stack: VecDeque<EditRecord>,
+  memory_cap: usize, //In bytes.
+  memory_used: usize
+}
+
+enum EditSource {
+  Region(Region, RegionOperation),
+  Blocks(ChunkBlockChanges), //Exact blocks to set (for undo, redo and pasting).
+}
+
+///An edit that is being applied to the terrain a few chunks at a time.
+pub(super) struct EditJob {
+  source: EditSource,
+  origin: EditOrigin,
+  pending_chunks: VecDeque<[i32; 3]>,
+  edited_chunks: HashMap<[i32; 3], u8>, //Chunks that have been changed, with the sides that had blocks changed on them (as BlockSide bit flags).
+  replaced: EditRecord
+}
+
+impl Region {
+  pub fn from_corners(a: Vector3<i32>, b: Vector3<i32>) -> Self {
+    Self {
+      lo: a.zip(b, i32::min),
+      hi: a.zip(b, i32::max)
+    }
+  }
+
+  pub fn size(&self) -> Vector3<i32> {
+    self.hi - self.lo + Vector3::from([1; 3])
+  }
+
+  ///How many blocks are in the region.
+  pub fn block_count(&self) -> u64 {
+    let size = self.size();
+    size.x as u64 * size.y as u64 * size.z as u64
+  }
+
+  pub fn contains(&self, pos: Vector3<i32>) -> bool {
+    (0..3).all(|i| self.lo[i] <= pos[i] && pos[i] <= self.hi[i])
+  }
+
+  ///Gets the IDs of all chunks that the region overlaps.
+  pub fn chunk_ids(&self) -> impl Iterator<Item = [i32; 3]> {
+    let (lo, hi) = (ChunkedTerrain::pos_to_chunk_id(self.lo), ChunkedTerrain::pos_to_chunk_id(self.hi));
+    iproduct!(lo.x..=hi.x, lo.y..=hi.y, lo.z..=hi.z).map(|(x, y, z)| [x, y, z])
+  }
+
+  ///Gets the part of the region that is inside a chunk (in absolute coordinates).
+  pub fn chunk_intersection(&self, chunk_id: [i32; 3]) -> Option<Region> {
+    let chunk_lo = Vector3::from(chunk_id) * CHUNK_SIZE_I32;
+    let chunk_hi = chunk_lo + Vector3::from([CHUNK_SIZE_I32 - 1; 3]);
+    let lo = self.lo.zip(chunk_lo, i32::max);
+    let hi = self.hi.zip(chunk_hi, i32::min);
+
+    if (0..3).all(|i| lo[i] <= hi[i]) {
+      Some(Region { lo, hi })
+    } else {
+      None
+    }
+  }
+}
+
+impl RegionOperation {
+  ///Gets the new block at a position in the region, or None if the block should not change.
+  fn get_block(&self, region: &Region, pos: Vector3<i32>, old_block: Block) -> Option<Block> {
+    match *self {
+      RegionOperation::Fill(block) => Some(block),
+      RegionOperation::Replace(from, to) => if old_block == from {Some(to)} else {None},
+      RegionOperation::Hollow(block) => {
+        let is_shell = (0..3).any(|i| pos[i] == region.lo[i] || pos[i] == region.hi[i]);
+        Some(if is_shell {block} else {Block::Air})
+      },
+      RegionOperation::Walls(block) => {
+        let is_wall = [0, 2].into_iter().any(|i| pos[i] == region.lo[i] || pos[i] == region.hi[i]);
+        if is_wall {Some(block)} else {None}
+      },
+      RegionOperation::Sphere(block) => {
+        //Use the centres of blocks so the sphere is symmetrical.
+        let radius = region.size().map(|v| v as f32 / 2.0);
+        let centre = region.lo.map(|v| v as f32) + radius;
+        let dist_sq: f32 = (0..3).map(|i| ((pos[i] as f32 + 0.5 - centre[i]) / radius[i]).powi(2)).sum();
+        if dist_sq <= 1.0 {Some(block)} else {None}
+      },
+    }
+  }
+}
+
+impl EditRecord {
+  pub fn single_block(pos: Vector3<i32>, block: Block) -> Self {
+    Self::from_blocks([(pos, block)])
+  }
+
+  ///Creates a record from blocks at absolute positions.
+  pub fn from_blocks(blocks: impl IntoIterator<Item = (Vector3<i32>, Block)>) -> Self {
+    let mut record = Self::default();
+    for (pos, block) in blocks {
+      let chunk_id = ChunkedTerrain::pos_to_chunk_id(pos);
+      let rel_pos = pos - chunk_id * CHUNK_SIZE_I32;
+      let index = Chunk::rel_pos_to_index(rel_pos.x, rel_pos.y, rel_pos.z).unwrap() as u16;
+      record.blocks.entry(chunk_id.into()).or_default().push((index, block));
+    }
+    record
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.blocks.is_empty()
+  }
+
+  ///Rough amount of memory used by the record.
+  pub fn size_bytes(&self) -> usize {
+    self.blocks.values()
+      .map(|changes| changes.len() * std::mem::size_of::<(u16, Block)>() + std::mem::size_of::<([i32; 3], Vec<(u16, Block)>)>())
+      .sum()
+  }
+}
+
+impl EditHistory {
+  pub fn new(memory_cap: usize) -> Self {
+    Self {
+      undo_stack: VecDeque::new(),
+      redo_stack: VecDeque::new(),
+      memory_cap,
+      memory_used: 0
+    }
+  }
+
+  ///Adds the record of a finished edit. New edits clear the redo stack.
+  pub fn push(&mut self, origin: EditOrigin, record: EditRecord) {
+    if record.is_empty() {
+      return;
+    }
+
+    self.memory_used += record.size_bytes();
+    match origin {
+      EditOrigin::Edit => {
+        for redo in self.redo_stack.drain(..) {
+          self.memory_used -= redo.size_bytes();
+        }
+        self.undo_stack.push_back(record);
+      },
+      EditOrigin::Undo => self.redo_stack.push_back(record),
+      EditOrigin::Redo => self.undo_stack.push_back(record),
+    }
+    self.trim();
+  }
+
+  pub fn take_undo(&mut self) -> Option<EditRecord> {
+    let record = self.undo_stack.pop_back()?;
+    self.memory_used -= record.size_bytes();
+    Some(record)
+  }
+
+  pub fn take_redo(&mut self) -> Option<EditRecord> {
+    let record = self.redo_stack.pop_back()?;
+    self.memory_used -= record.size_bytes();
+    Some(record)
+  }
+
+  pub fn set_memory_cap(&mut self, memory_cap: usize) {
+    self.memory_cap = memory_cap;
+    self.trim();
+  }
+
+  pub fn undo_count(&self) -> usize {
+    self.undo_stack.len()
+  }
+
+  pub fn redo_count(&self) -> usize {
+    self.redo_stack.len()
+  }
+
+  pub fn memory_used(&self) -> usize {
+    self.memory_used
+  }
+
+  pub fn memory_cap(&self) -> usize {
+    self.memory_cap
+  }
+
+  ///Drops the oldest records until the history fits in the memory cap.
+  fn trim(&mut self) {
+    while self.memory_used > self.memory_cap {
+      //The oldest record is at the front of the undo stack, or the front of the redo stack (furthest from being redone).
+      let record = match self.undo_stack.pop_front() {
+        Some(record) => record,
+        None => match self.redo_stack.pop_front() {
+          Some(record) => record,
+          None => break,
+        },
+      };
+      self.memory_used -= record.size_bytes();
+    }
+  }
+}
+
+impl EditJob {
+  pub fn new(region: Region, operation: RegionOperation) -> Self {
+    Self::from_source(EditSource::Region(region, operation), region.chunk_ids().collect(), EditOrigin::Edit)
+  }
+
+  ///Sets the blocks in a record (to undo or redo an edit, or to paste).
+  pub fn from_record(record: EditRecord, origin: EditOrigin) -> Self {
+    let chunks = record.blocks.keys().copied().collect();
+    Self::from_source(EditSource::Blocks(record.blocks), chunks, origin)
+  }
+
+  fn from_source(source: EditSource, pending_chunks: VecDeque<[i32; 3]>, origin: EditOrigin) -> Self {
+    Self {
+      source,
+      origin,
+      pending_chunks,
+      edited_chunks: HashMap::new(),
+      replaced: EditRecord::default()
+    }
+  }
+
+  pub fn next_chunk(&mut self) -> Option<[i32; 3]> {
+    self.pending_chunks.pop_front()
+  }
+
+  ///Puts a chunk at the back of the queue so it can be edited once it has generated.
+  pub fn defer_chunk(&mut self, chunk_id: [i32; 3]) {
+    self.pending_chunks.push_back(chunk_id);
+  }
+
+  pub fn pending_chunk_count(&self) -> usize {
+    self.pending_chunks.len()
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.pending_chunks.is_empty()
+  }
+
+  ///Applies the edit to the blocks of a chunk, remembering the blocks that were replaced. Returns true if any blocks changed.
+  pub fn apply_to_blocks(&mut self, chunk_id: [i32; 3], blocks: &mut [Block]) -> bool {
+    let new_blocks: Vec<(usize, Block)> = match &self.source {
+      EditSource::Region(region, operation) => {
+        let area = match region.chunk_intersection(chunk_id) {
+          Some(area) => area,
+          None => return false, //Nothing to do.
+        };
+        let chunk_pos = Vector3::from(chunk_id) * CHUNK_SIZE_I32;
+
+        iproduct!(area.lo.x..=area.hi.x, area.lo.y..=area.hi.y, area.lo.z..=area.hi.z).filter_map(|(x, y, z)| {
+          let pos = Vector3::from([x, y, z]);
+          let rel_pos = pos - chunk_pos;
+          let index = Chunk::rel_pos_to_index(rel_pos.x, rel_pos.y, rel_pos.z).unwrap();
+          operation.get_block(region, pos, blocks[index]).map(|block| (index, block))
+        }).collect()
+      },
+      EditSource::Blocks(chunks) => {
+        chunks.get(&chunk_id).into_iter().flatten().map(|&(index, block)| (index as usize, block)).collect()
+      },
+    };
+
+    let mut edited_sides = 0u8;
+    let mut replaced = Vec::new();
+    for (index, new_block) in new_blocks {
+      let old_block = blocks[index];
+      if new_block != old_block {
+        blocks[index] = new_block;
+        edited_sides |= get_edge_sides(index_to_rel_pos(index));
+        replaced.push((index as u16, old_block));
+      }
+    }
+
+    if replaced.is_empty() {
+      return false;
+    }
+    *self.edited_chunks.entry(chunk_id).or_insert(0) |= edited_sides;
+    self.replaced.blocks.entry(chunk_id).or_default().extend(replaced);
+    true
+  }
+
+  ///Applies the edit to a loaded chunk. Returns false if the chunk blocks have not been generated yet.
+  pub fn apply_to_chunk(&mut self, chunk_id: [i32; 3], chunk: &Chunk) -> bool {
+    chunk.edit_blocks(|blocks| self.apply_to_blocks(chunk_id, blocks))
+  }
+
+  ///Gets the chunks that were edited, along with the sides of the chunk that had blocks changed on them.
+  pub fn edited_chunks(&self) -> impl Iterator<Item = (&[i32; 3], &u8)> {
+    self.edited_chunks.iter()
+  }
+
+  ///Finishes the job, giving back where it came from and the blocks it replaced.
+  pub fn into_record(self) -> (EditOrigin, EditRecord) {
+    (self.origin, self.replaced)
+  }
+}
+
+///Converts an index into the chunk block array back into a chunk-relative position.
+fn index_to_rel_pos(index: usize) -> Vector3<i32> {
+  let size = CHUNK_SIZE_I32 as usize;
+  Vector3::new((index / (size * size)) as i32, (index / size % size) as i32, (index % size) as i32)
+}
+
+///Gets the chunk sides (as BlockSide bit flags) that a chunk-relative position is touching.
+fn get_edge_sides(rel_pos: Vector3<i32>) -> u8 {
+  const SIDES: [(BlockSide, BlockSide); 3] = [ //Low side and high side for each dimension.
+    (BlockSide::Left, BlockSide::Right),
+    (BlockSide::Below, BlockSide::Above),
+    (BlockSide::Front, BlockSide::Back),
+  ];
+
+  SIDES.iter().enumerate().fold(0u8, |flags, (i, (lo_side, hi_side))| {
+    if rel_pos[i] == 0 {
+      flags | 1u8 << *lo_side as u8
+    } else if rel_pos[i] == CHUNK_SIZE_I32 - 1 {
+      flags | 1u8 << *hi_side as u8
+    } else {
+      flags
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use cgmath::Vector3;
+
+  use crate::world::{block::Block, chunkedterrain::CHUNK_LENGTH};
+
+  use super::{EditHistory, EditJob, EditOrigin, EditRecord, Region, RegionOperation};
+
+  #[test]
+  fn test_region_corners() {
+    let region = Region::from_corners(Vector3::new(5, -2, 3), Vector3::new(-1, 4, 3));
+    assert_eq!(region.lo, Vector3::new(-1, -2, 3));
+    assert_eq!(region.hi, Vector3::new(5, 4, 3));
+    assert_eq!(region.size(), Vector3::new(7, 7, 1));
+  }
+
+  #[test]
+  fn test_region_chunk_ids() {
+    let region = Region::from_corners(Vector3::new(-1, 0, 0), Vector3::new(32, 0, 0));
+    assert_eq!(region.chunk_ids().count(), 3); //Chunks -1, 0 and 1 on the x axis.
+  }
+
+  #[test]
+  fn test_hollow_and_walls() {
+    let region = Region::from_corners(Vector3::new(0, 0, 0), Vector3::new(2, 2, 2));
+    let centre = Vector3::new(1, 1, 1);
+    let top = Vector3::new(1, 2, 1);
+    let side = Vector3::new(0, 1, 1);
+
+    let hollow = RegionOperation::Hollow(Block::Stone);
+    assert_eq!(hollow.get_block(&region, centre, Block::Grass), Some(Block::Air));
+    assert_eq!(hollow.get_block(&region, top, Block::Grass), Some(Block::Stone));
+
+    let walls = RegionOperation::Walls(Block::Stone);
+    assert_eq!(walls.get_block(&region, top, Block::Grass), None);
+    assert_eq!(walls.get_block(&region, side, Block::Grass), Some(Block::Stone));
+  }
+
+  #[test]
+  fn test_sphere() {
+    let region = Region::from_corners(Vector3::new(0, 0, 0), Vector3::new(4, 4, 4));
+    let sphere = RegionOperation::Sphere(Block::Stone);
+    assert_eq!(sphere.get_block(&region, Vector3::new(2, 2, 2), Block::Air), Some(Block::Stone));
+    assert_eq!(sphere.get_block(&region, Vector3::new(2, 2, 0), Block::Air), Some(Block::Stone));
+    assert_eq!(sphere.get_block(&region, Vector3::new(0, 0, 0), Block::Air), None); //Corner.
+  }
+
+  #[test]
+  fn test_restore_exact() {
+    let original: Vec<Block> = (0..CHUNK_LENGTH).map(|i| Block::ALL[i % Block::ALL.len()]).collect();
+    let mut blocks = original.clone();
+
+    let mut fill = EditJob::new(Region::from_corners(Vector3::new(3, 3, 3), Vector3::new(40, 20, 10)), RegionOperation::Fill(Block::Cloud));
+    fill.apply_to_blocks([0, 0, 0], &mut blocks);
+    let (_, record) = fill.into_record();
+    assert!(blocks != original);
+
+    let mut undo = EditJob::from_record(record, EditOrigin::Undo);
+    undo.apply_to_blocks([0, 0, 0], &mut blocks);
+    assert!(blocks == original);
+
+    let (origin, redo_record) = undo.into_record();
+    assert_eq!(origin, EditOrigin::Undo);
+    let mut redo = EditJob::from_record(redo_record, EditOrigin::Redo);
+    redo.apply_to_blocks([0, 0, 0], &mut blocks);
+    assert_eq!(blocks[0], original[0]);
+    assert_eq!(blocks[3 * 1024 + 3 * 32 + 3], Block::Cloud);
+  }
+
+  #[test]
+  fn test_history_memory_cap() {
+    let record = |x| EditRecord::single_block(Vector3::new(x, 0, 0), Block::Stone);
+    let record_size = record(0).size_bytes();
+    let mut history = EditHistory::new(record_size * 3);
+
+    for x in 0..5 {
+      history.push(EditOrigin::Edit, record(x));
+    }
+    assert_eq!(history.undo_count(), 3); //Oldest ones get dropped.
+
+    let undone = history.take_undo().unwrap();
+    history.push(EditOrigin::Undo, undone);
+    assert_eq!((history.undo_count(), history.redo_count()), (2, 1));
+
+    history.push(EditOrigin::Edit, record(10)); //New edits clear the redo stack.
+    assert_eq!((history.undo_count(), history.redo_count()), (3, 0));
+    assert_eq!(history.memory_used(), record_size * 3);
+  }
+}
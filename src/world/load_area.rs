@@ -0,0 +1,98 @@
+use std::{ops::RangeInclusive, str::FromStr};
+
+use cgmath::Vector3;
+
+///The shape of the area of chunks that are loaded around the player.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadShape {
+  Sphere, //Actually an ellipsoid if the vertical radius is different.
+  Cylinder
+}
+
+///Which chunks are loaded around the player's chunk. Radiuses are in chunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoadArea {
+  pub shape: LoadShape,
+  pub radius: u32, //Horizontal radius.
+  pub vertical_radius: u32
+}
+
+impl LoadArea {
+  ///Checks if the chunk at an offset from the player's chunk should be loaded.
+  pub fn contains(&self, offset: Vector3<i32>) -> bool {
+    self.column_range(offset.x, offset.z).is_some_and(|range| range.contains(&offset.y))
+  }
+
+  ///Gets the range of chunk Y offsets that are loaded in the column at a horizontal offset, or None if the column is outside the area.
+  pub fn column_range(&self, dx: i32, dz: i32) -> Option<RangeInclusive<i32>> {
+    let r_sq = (self.radius as i64).pow(2);
+    let horizontal_sq = (dx as i64).pow(2) + (dz as i64).pow(2);
+    if horizontal_sq > r_sq {
+      return None;
+    }
+
+    let v = self.vertical_radius as i32;
+    let height = match self.shape {
+      LoadShape::Cylinder => v,
+      LoadShape::Sphere => {
+        //Highest y where (x² + z²)/r² + y²/v² <= 1 (multiplied out so it stays in integers).
+        let limit = (r_sq - horizontal_sq) * (v as i64).pow(2);
+        (0..=v).take_while(|y| (*y as i64).pow(2) * r_sq <= limit).last().unwrap_or(0)
+      },
+    };
+
+    Some(-height..=height)
+  }
+
+  ///Gets the horizontal offsets of every column in the area along with the range of Y offsets that are loaded in it.
+  pub fn columns(&self) -> impl Iterator<Item = ([i32; 2], RangeInclusive<i32>)> + '_ {
+    let r = self.radius as i32;
+    (-r..=r).flat_map(move |dx| (-r..=r).filter_map(move |dz| {
+      self.column_range(dx, dz).map(|range| ([dx, dz], range))
+    }))
+  }
+
+  pub fn chunk_count(&self) -> usize {
+    self.columns().map(|(_, range)| range.count()).sum()
+  }
+}
+
+impl FromStr for LoadShape {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      "sphere" => Ok(Self::Sphere),
+      "cylinder" => Ok(Self::Cylinder),
+      _ => Err(())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use cgmath::Vector3;
+
+  use super::{LoadArea, LoadShape};
+
+  #[test]
+  fn test_cylinder() {
+    let area = LoadArea { shape: LoadShape::Cylinder, radius: 4, vertical_radius: 2 };
+    assert!(area.contains(Vector3::new(4, 2, 0)));
+    assert!(area.contains(Vector3::new(-2, -2, 3))); //4 + 9 <= 16
+    assert!(!area.contains(Vector3::new(3, 0, 3))); //Corner of the cube.
+    assert!(!area.contains(Vector3::new(0, 3, 0)));
+  }
+
+  #[test]
+  fn test_sphere() {
+    let area = LoadArea { shape: LoadShape::Sphere, radius: 4, vertical_radius: 2 };
+    assert_eq!(area.column_range(0, 0), Some(-2..=2));
+    assert_eq!(area.column_range(4, 0), Some(0..=0));
+    assert_eq!(area.column_range(3, 0), Some(-1..=1));
+    assert!(!area.contains(Vector3::new(0, 3, 0)));
+
+    let cube_count = 9 * 9 * 5;
+    assert!(area.chunk_count() < cube_count / 2); //A sphere is roughly half the volume of a cube.
+  }
+}
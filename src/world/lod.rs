@@ -0,0 +1,244 @@
+use std::{collections::HashSet, sync::{OnceLock, atomic::{AtomicBool, Ordering}}};
+
+use itertools::iproduct;
+use noise::Perlin;
+use wgpu::{Device, Queue};
+
+use crate::renderer::{mesh_arena::MeshArena, mesh_uploader::MeshUpload};
+
+use super::{block::{Block, BlockSide}, chunk::{ChunkMeshRange, MeshSlot}, chunkedterrain::{surface_height, CHUNK_SIZE}, load_area::LoadArea, mesher::{push_quad, ChunkMeshData}};
+
+pub const MAX_LOD_LEVELS: u8 = 3; //The furthest ring has one cell every 8 blocks.
+const TILE_CELLS: i32 = CHUNK_SIZE as i32; //Tiles are always 32x32 cells, so the packed vertices still fit.
+const SKIRT_DEPTH: i32 = 2; //How far (in cells) the edges of a tile hang down to hide the gaps between tiles with different scales.
+const MAX_CELL_HEIGHT: i32 = 63; //The highest y a ChunkVertex can have.
+
+///Identifies an LOD tile. Tiles at level n are 2^n chunks wide with one cell every 2^n blocks.
+///Level 0 tiles are a single chunk column and are only used to fill the gaps around the loaded chunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LodTileId {
+  pub level: u8,
+  pub x: i32, //In tiles of this level.
+  pub z: i32
+}
+
+impl LodTileId {
+  ///How many blocks wide each cell is.
+  pub fn scale(&self) -> i32 {
+    1 << self.level
+  }
+
+  fn children(&self) -> [LodTileId; 4] {
+    [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dz)| LodTileId {
+      level: self.level - 1,
+      x: self.x * 2 + dx,
+      z: self.z * 2 + dz
+    })
+  }
+
+  ///The chunk x and z of the column in the middle of the tile.
+  pub fn centre_column(&self) -> [i32; 2] {
+    [self.x, self.z].map(|v| v * self.scale() + self.scale() / 2)
+  }
+
+  ///How far the closest point of the tile is from the middle of a chunk column, in chunks.
+  fn distance_from(&self, column: [i32; 2]) -> f32 {
+    let size = self.scale() as f32;
+    let [dx, dz] = [(self.x, column[0]), (self.z, column[1])].map(|(tile, column)| {
+      let (min, centre) = (tile as f32 * size, column as f32 + 0.5);
+      (min - centre).max(centre - (min + size)).max(0.0)
+    });
+    (dx * dx + dz * dz).sqrt()
+  }
+}
+
+///A coarse mesh of the surface made straight from the heightmap, without generating any blocks. Caves and clouds are left out.
+///Starts empty and gets generated by a worker.
+pub struct LodTile {
+  id: LodTileId,
+  origin: OnceLock<[i32; 3]>, //Block position of the tile's lowest corner. Set when it's generated.
+  mesh: MeshSlot,
+  cancelled: AtomicBool
+}
+
+impl LodTile {
+  pub fn new(id: LodTileId) -> Self {
+    Self {
+      id,
+      origin: OnceLock::new(),
+      mesh: MeshSlot::new(),
+      cancelled: AtomicBool::new(false)
+    }
+  }
+
+  ///Samples the surface in the middle of every cell and builds the mesh. The mesh still needs uploading.
+  ///Returns false if the tile was cancelled or has already been generated.
+  pub fn generate(&self, gen: &Perlin) -> bool {
+    if self.is_cancelled() || self.origin.get().is_some() {
+      return false;
+    }
+    let id = self.id;
+    let scale = id.scale();
+    let min_block = [id.x, id.z].map(|v| v * TILE_CELLS * scale);
+
+    //Includes a border of cells from the neighbouring tiles. The top of each cell is rounded to the nearest cell.
+    let heights: Vec<i32> = iproduct!(-1..=TILE_CELLS, -1..=TILE_CELLS).map(|(a, b)| {
+      let height = surface_height(gen, min_block[0] + a * scale + scale / 2, min_block[1] + b * scale + scale / 2);
+      (height + 1 + scale / 2).div_euclid(scale)
+    }).collect();
+    let base = heights.iter().min().unwrap() - SKIRT_DEPTH;
+    let relative: Vec<i32> = heights.iter().map(|height| (height - base).min(MAX_CELL_HEIGHT)).collect();
+
+    let mesh = build_lod_mesh(&relative);
+    if self.origin.set([min_block[0], base * scale, min_block[1]]).is_err() {
+      return false;
+    }
+    self.mesh.set_pending(mesh);
+    true
+  }
+
+  pub fn get_id(&self) -> LodTileId {
+    self.id
+  }
+
+  pub fn get_origin(&self) -> Option<[i32; 3]> {
+    self.origin.get().copied()
+  }
+
+  pub fn get_mesh_fast(&self) -> Option<ChunkMeshRange> {
+    self.mesh.get_fast()
+  }
+
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed)
+  }
+}
+
+impl MeshUpload for LodTile {
+  fn is_cancelled(&self) -> bool {
+    LodTile::is_cancelled(self)
+  }
+
+  fn upload_mesh(&self, arena: &mut MeshArena, device: &Device, queue: &Queue) {
+    self.mesh.upload(arena, device, queue);
+  }
+}
+
+///Picks the tiles for `levels` rings around the loaded chunks. Each ring reaches twice as far as the one inside it and uses tiles twice as big.
+///The tiles don't overlap each other or any loaded column.
+pub fn select_lod_tiles(load_area: &LoadArea, centre: [i32; 2], levels: u8) -> HashSet<LodTileId> {
+  let mut tiles = HashSet::new();
+  if levels == 0 {
+    return tiles;
+  }
+
+  let size = 1 << levels;
+  let outer = load_area.radius as i32 * size;
+  let range = |c: i32| (c - outer).div_euclid(size)..=(c + outer).div_euclid(size);
+  for (x, z) in iproduct!(range(centre[0]), range(centre[1])) {
+    let tile = LodTileId { level: levels, x, z };
+    if tile.distance_from(centre) <= outer as f32 {
+      add_lod_tile(&mut tiles, tile, load_area, centre);
+    }
+  }
+
+  tiles
+}
+
+///Adds the tile, or splits it into smaller tiles if it's close enough to the next ring in.
+fn add_lod_tile(tiles: &mut HashSet<LodTileId>, tile: LodTileId, load_area: &LoadArea, centre: [i32; 2]) {
+  if tile.level == 0 {
+    if load_area.column_range(tile.x - centre[0], tile.z - centre[1]).is_none() {
+      tiles.insert(tile);
+    }
+  } else if tile.distance_from(centre) <= (load_area.radius << (tile.level - 1)) as f32 {
+    for child in tile.children() {
+      add_lod_tile(tiles, child, load_area, centre);
+    }
+  } else {
+    tiles.insert(tile);
+  }
+}
+
+///Builds a tile's mesh from the top of each cell (in cells above the bottom of the tile).
+///`heights` includes a border of one cell around the tile (in x, then z order) so the sides facing the neighbouring cells can be added.
+///Sides on the edges of the tile hang down as skirts so there aren't any gaps next to tiles with a different scale.
+pub fn build_lod_mesh(heights: &[i32]) -> ChunkMeshData {
+  const SIDES: [BlockSide; 4] = [BlockSide::Right, BlockSide::Left, BlockSide::Back, BlockSide::Front];
+  let width = TILE_CELLS + 2;
+  let height_at = |a: i32, b: i32| heights[((a + 1) * width + b + 1) as usize];
+  let cell_range = 0..TILE_CELLS;
+
+  let mut mesh = ChunkMeshData::default();
+  for (a, b) in iproduct!(cell_range.clone(), cell_range.clone()) {
+    let top = height_at(a, b);
+    push_quad(&mut mesh, BlockSide::Above, [a as usize, top as usize - 1, b as usize], [1, 1, 1], Block::Grass);
+
+    for side in SIDES {
+      let offset = side.get_offset();
+      let (na, nb) = (a + offset.x, b + offset.z);
+      let neighbour = height_at(na, nb);
+      let bottom = if cell_range.contains(&na) && cell_range.contains(&nb) {
+        neighbour
+      } else {
+        (neighbour.min(top) - SKIRT_DEPTH).max(0)
+      };
+      if bottom < top {
+        push_quad(&mut mesh, side, [a as usize, bottom as usize, b as usize], [1, (top - bottom) as usize, 1], Block::Stone);
+      }
+    }
+  }
+
+  mesh
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::world::{block::BlockSide, load_area::{LoadArea, LoadShape}};
+
+  use super::{build_lod_mesh, select_lod_tiles, LodTileId, TILE_CELLS, SKIRT_DEPTH};
+
+  #[test]
+  fn test_flat_tile_has_skirts() {
+    let width = (TILE_CELLS + 2) as usize;
+    let mesh = build_lod_mesh(&vec![SKIRT_DEPTH + 1; width * width]);
+    let tops = TILE_CELLS * TILE_CELLS;
+    let skirts = TILE_CELLS * 4;
+    assert_eq!(mesh.vertices.len() as i32, (tops + skirts) * 4);
+    let lowest_skirt = mesh.vertices.iter().filter(|v| v.side() != BlockSide::Above).map(|v| v.position()[1]).min();
+    assert_eq!(lowest_skirt, Some(1));
+  }
+
+  #[test]
+  fn test_rings_cover_area_once() {
+    let load_area = LoadArea { shape: LoadShape::Cylinder, radius: 3, vertical_radius: 2 };
+    let centre = [5, -7];
+    let tiles = select_lod_tiles(&load_area, centre, 2);
+    assert!(tiles.iter().any(|tile| tile.level == 2));
+
+    //Every column within the full detail radius of the outer ring is covered by exactly one tile or is loaded.
+    for (x, z) in itertools::iproduct!(-9..=9, -9..=9) {
+      if x * x + z * z > 9 * 9 {
+        continue;
+      }
+      let column = [centre[0] + x, centre[1] + z];
+      let covering = tiles.iter().filter(|tile| {
+        let size = tile.scale();
+        column[0].div_euclid(size) == tile.x && column[1].div_euclid(size) == tile.z
+      }).count();
+      let loaded = load_area.column_range(x, z).is_some();
+      assert_eq!(covering + loaded as usize, 1, "Column {:?}", column);
+    }
+  }
+
+  #[test]
+  fn test_no_levels() {
+    let load_area = LoadArea { shape: LoadShape::Cylinder, radius: 3, vertical_radius: 2 };
+    assert!(select_lod_tiles(&load_area, [0, 0], 0).is_empty());
+    assert_eq!(LodTileId { level: 3, x: 0, z: 0 }.scale(), 8);
+  }
+}
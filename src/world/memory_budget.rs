@@ -0,0 +1,142 @@
+use super::load_area::LoadArea;
+
+const GROW_MARGIN: f32 = 0.9; //The render distance only grows back if the bigger area would use less than this much of the budget, so it doesn't keep flipping.
+const ARENA_HEADROOM: f32 = 0.75; //Leaves space in the mesh arena for gaps between meshes.
+
+///Limits on how much memory the terrain can use, in bytes. 0 means there is no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryBudget {
+  pub blocks: usize,
+  pub meshes: usize //Space in the mesh arena on the GPU.
+}
+
+///How much memory the terrain is using, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+  pub blocks: usize, //Includes saved and cached chunks.
+  pub meshes: usize, //Everything in the mesh arena, including LOD tiles and cached chunks.
+  pub loaded_blocks: usize, //The parts of blocks and meshes used by loaded chunks. Only these change with the render distance.
+  pub loaded_meshes: usize,
+  pub chunks_with_blocks: usize,
+  pub chunks_with_meshes: usize
+}
+
+impl MemoryBudget {
+  ///Checks if the usage fits in the budget, with each limit multiplied by `margin`.
+  fn fits(&self, usage: &MemoryUsage, margin: f32) -> bool {
+    let fits = |used: usize, budget: usize| budget == 0 || used as f32 <= budget as f32 * margin;
+    fits(usage.blocks, self.blocks) && fits(usage.meshes, self.meshes)
+  }
+
+  ///Makes sure the mesh budget is small enough that the mesh arena never fills up. `max` is the most the arena can hold.
+  pub fn clamp_meshes(&mut self, max: usize) {
+    let max = (max as f32 * ARENA_HEADROOM) as usize;
+    self.meshes = if self.meshes == 0 {max} else {self.meshes.min(max)};
+  }
+}
+
+impl MemoryUsage {
+  ///Guesses how much memory would be used if every chunk in `load_area` was loaded with blocks and a mesh, using the average size of the loaded ones.
+  ///Memory that isn't used by loaded chunks stays the same.
+  fn estimate_for(&self, load_area: &LoadArea) -> MemoryUsage {
+    let chunk_count = load_area.chunk_count();
+    //Gets the new total and loaded memory.
+    let estimate = |total: usize, loaded: usize, count: usize| {
+      let loaded_estimate = loaded.checked_div(count).map_or(0, |average| average * chunk_count);
+      (total.saturating_sub(loaded) + loaded_estimate, loaded_estimate)
+    };
+    let (blocks, loaded_blocks) = estimate(self.blocks, self.loaded_blocks, self.chunks_with_blocks);
+    let (meshes, loaded_meshes) = estimate(self.meshes, self.loaded_meshes, self.chunks_with_meshes);
+    MemoryUsage {
+      blocks,
+      meshes,
+      loaded_blocks,
+      loaded_meshes,
+      chunks_with_blocks: chunk_count,
+      chunks_with_meshes: chunk_count
+    }
+  }
+}
+
+///Picks the render distance to use so the loaded chunks fit in the budget. Never goes above the radius of `load_area` or below 1.
+///Shrinking the radius unloads the outer ring, so the farthest chunks are always evicted first. It grows back one ring at a time.
+pub fn fit_radius(load_area: &LoadArea, current_radius: u32, usage: &MemoryUsage, budget: &MemoryBudget) -> u32 {
+  let with_radius = |radius: u32| LoadArea { radius, ..*load_area };
+  let mut radius = current_radius.clamp(1, load_area.radius.max(1));
+  while radius > 1 && !budget.fits(&usage.estimate_for(&with_radius(radius)), 1.0) {
+    radius -= 1;
+  }
+  if radius == current_radius && radius < load_area.radius && budget.fits(&usage.estimate_for(&with_radius(radius + 1)), GROW_MARGIN) {
+    radius += 1;
+  }
+  radius
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::world::load_area::{LoadArea, LoadShape};
+
+  use super::{fit_radius, MemoryBudget, MemoryUsage};
+
+  const AREA: LoadArea = LoadArea { shape: LoadShape::Cylinder, radius: 8, vertical_radius: 1 };
+
+  fn usage(radius: u32, chunk_bytes: usize) -> MemoryUsage {
+    let count = LoadArea { radius, ..AREA }.chunk_count();
+    MemoryUsage {
+      blocks: count * chunk_bytes,
+      meshes: count * chunk_bytes,
+      loaded_blocks: count * chunk_bytes,
+      loaded_meshes: count * chunk_bytes,
+      chunks_with_blocks: count,
+      chunks_with_meshes: count
+    }
+  }
+
+  #[test]
+  fn test_no_budget() {
+    assert_eq!(fit_radius(&AREA, 8, &usage(8, 1000), &MemoryBudget::default()), 8);
+    assert_eq!(fit_radius(&AREA, 3, &usage(3, 1000), &MemoryBudget::default()), 4); //Grows back one ring at a time.
+  }
+
+  #[test]
+  fn test_shrinks_to_fit() {
+    let budget = MemoryBudget { blocks: usage(5, 1000).blocks, meshes: 0 };
+    assert_eq!(fit_radius(&AREA, 8, &usage(8, 1000), &budget), 5);
+    assert_eq!(fit_radius(&AREA, 5, &usage(5, 1000), &budget), 5); //Radius 6 doesn't fit, so it stays.
+
+    let tiny = MemoryBudget { blocks: 1, meshes: 1 };
+    assert_eq!(fit_radius(&AREA, 8, &usage(8, 1000), &tiny), 1);
+  }
+
+  #[test]
+  fn test_unloaded_memory_counts() {
+    //Saved chunks and LOD tiles don't go away when the radius shrinks, so there is less room for loaded chunks.
+    let budget = MemoryBudget { blocks: usage(5, 1000).blocks, meshes: usage(5, 1000).meshes };
+    let mut with_saved = usage(8, 1000);
+    with_saved.blocks += usage(2, 1000).blocks;
+    assert_eq!(fit_radius(&AREA, 8, &with_saved, &budget), 4);
+
+    let mut with_lod = usage(8, 1000);
+    with_lod.meshes += budget.meshes;
+    assert_eq!(fit_radius(&AREA, 8, &with_lod, &budget), 1);
+  }
+
+  #[test]
+  fn test_clamp_meshes() {
+    let mut budget = MemoryBudget { blocks: 0, meshes: 0 };
+    budget.clamp_meshes(400);
+    assert_eq!(budget.meshes, 300);
+    budget.meshes = 100;
+    budget.clamp_meshes(400);
+    assert_eq!(budget.meshes, 100);
+  }
+
+  #[test]
+  fn test_grows_with_margin() {
+    let budget = MemoryBudget { blocks: 0, meshes: usage(6, 1000).meshes };
+    assert_eq!(fit_radius(&AREA, 5, &usage(5, 1000), &budget), 5); //Radius 6 would use all of the budget, which is over the margin.
+
+    let budget = MemoryBudget { blocks: 0, meshes: usage(7, 1000).meshes };
+    assert_eq!(fit_radius(&AREA, 5, &usage(5, 1000), &budget), 6);
+  }
+}
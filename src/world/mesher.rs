@@ -0,0 +1,289 @@
+use bytemuck_derive::{Zeroable, Pod};
+use itertools::iproduct;
+
+use super::{block::{Block, BlockSide, BlockSideVisibility}, chunkedterrain::{CHUNK_RANGE, CHUNK_SIZE}};
+
+///A vertex packed into 32 bits. The chunk position is given per draw and terrain.wgsl unpacks the rest.
+///Bits 0-17 are the x, y and z position in the chunk (6 bits each, because faces can be on the far edge at 32),
+///bits 18-20 are the BlockSide of the face and bits 21-28 are the block (used as an index into the colour palette).
+#[derive(Clone, Copy, Pod, Zeroable, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct ChunkVertex {
+  data: u32
+}
+
+const POSITION_BITS: u32 = 6;
+const POSITION_MASK: u32 = (1 << POSITION_BITS) - 1;
+const SIDE_SHIFT: u32 = POSITION_BITS * 3;
+const BLOCK_SHIFT: u32 = SIDE_SHIFT + 3;
+
+impl ChunkVertex {
+  pub fn new(position: [usize; 3], side: BlockSide, block: Block) -> Self {
+    let [x, y, z] = position.map(|p| p as u32 & POSITION_MASK);
+    Self {
+      data: x | y << POSITION_BITS | z << (POSITION_BITS * 2) | (side as u32) << SIDE_SHIFT | (block as u32) << BLOCK_SHIFT
+    }
+  }
+
+  pub fn position(&self) -> [u32; 3] {
+    [0, 1, 2].map(|i| self.data >> (POSITION_BITS * i) & POSITION_MASK)
+  }
+
+  pub fn side(&self) -> BlockSide {
+    BlockSide::try_from((self.data >> SIDE_SHIFT & 0b111) as u8).unwrap()
+  }
+
+  pub fn block_index(&self) -> u32 {
+    self.data >> BLOCK_SHIFT & 0xFF
+  }
+}
+
+///A chunk mesh on the CPU. Made on the worker threads and uploaded to the GPU by the renderer.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkMeshData {
+  pub vertices: Vec<ChunkVertex>,
+  pub indices: Vec<u32>
+}
+
+///How chunk meshes are built.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshAlgorithm {
+  Simple, //One quad for every visible face.
+  Greedy, //Merges faces next to each other into bigger quads.
+}
+
+const WINDING_ORDER: [u32; 6] = [0, 1, 2, 2, 3, 0];
+
+impl MeshAlgorithm {
+  pub fn build(&self, blocks: &[Block], block_vis: &[BlockSideVisibility]) -> ChunkMeshData {
+    match self {
+      MeshAlgorithm::Simple => build_mesh(blocks, block_vis),
+      MeshAlgorithm::Greedy => build_greedy_mesh(blocks, block_vis),
+    }
+  }
+}
+
+///Builds the mesh for a chunk from its blocks and their visible sides (both in x, then y, then z order).
+pub fn build_mesh(blocks: &[Block], block_vis: &[BlockSideVisibility]) -> ChunkMeshData {
+  let mut mesh = ChunkMeshData::default();
+
+  for ((x, y, z), (block, block_visibility)) in iproduct!(CHUNK_RANGE, CHUNK_RANGE, CHUNK_RANGE).zip(blocks.iter().zip(block_vis)) {
+    if block_visibility.is_invisible() {continue}; //Skip invisible blocks.
+
+    for side_i in 0..6 { //Corresponds to BlockSide values.
+      let side = BlockSide::try_from(side_i).unwrap();
+
+      if !block_visibility.get_visible(side) {continue}; //Skip this side if it is not visible.
+      push_quad(&mut mesh, side, [x, y, z], [1, 1, 1], *block);
+    }
+  }
+
+  mesh
+}
+
+///Same as build_mesh, but faces that are next to each other on the same side with the same block get merged into rectangles.
+///Only faces that would look the same are merged, so anything added per face later (like textures) needs adding to the merge check.
+pub fn build_greedy_mesh(blocks: &[Block], block_vis: &[BlockSideVisibility]) -> ChunkMeshData {
+  let mut mesh = ChunkMeshData::default();
+  let mut mask: Vec<Option<Block>> = vec![None; CHUNK_SIZE * CHUNK_SIZE]; //Visible faces in the current layer, indexed by [u][v].
+
+  for side_i in 0..6 {
+    let side = BlockSide::try_from(side_i).unwrap();
+    let d = side_i as usize / 2; //The axis the side faces along.
+    let (u, v) = ((d + 1) % 3, (d + 2) % 3); //The axes the faces lie along.
+
+    for layer in CHUNK_RANGE {
+      for (a, b) in iproduct!(CHUNK_RANGE, CHUNK_RANGE) {
+        let mut pos = [0; 3];
+        pos[d] = layer;
+        pos[u] = a;
+        pos[v] = b;
+        let index = pos[0] * CHUNK_SIZE * CHUNK_SIZE + pos[1] * CHUNK_SIZE + pos[2];
+        mask[a * CHUNK_SIZE + b] = if block_vis[index].get_visible(side) {Some(blocks[index])} else {None};
+      }
+
+      for a in CHUNK_RANGE {
+        let mut b = 0;
+        while b < CHUNK_SIZE {
+          let block = match mask[a * CHUNK_SIZE + b] {
+            Some(block) => block,
+            None => {
+              b += 1;
+              continue;
+            },
+          };
+
+          //Grow along v first, then along u while the whole row matches.
+          let mut height = 1;
+          while b + height < CHUNK_SIZE && mask[a * CHUNK_SIZE + b + height] == Some(block) {
+            height += 1;
+          }
+          let mut width = 1;
+          while a + width < CHUNK_SIZE && (b..b + height).all(|bb| mask[(a + width) * CHUNK_SIZE + bb] == Some(block)) {
+            width += 1;
+          }
+          for (aa, bb) in iproduct!(a..a + width, b..b + height) { //Don't use these faces again.
+            mask[aa * CHUNK_SIZE + bb] = None;
+          }
+
+          let mut origin = [0; 3];
+          origin[d] = layer;
+          origin[u] = a;
+          origin[v] = b;
+          let mut size = [1; 3];
+          size[u] = width;
+          size[v] = height;
+          push_quad(&mut mesh, side, origin, size, block);
+          b += height;
+        }
+      }
+    }
+  }
+
+  mesh
+}
+
+///Adds a quad covering the side of a box of blocks. The origin is the lowest block in the box.
+pub(super) fn push_quad(mesh: &mut ChunkMeshData, side: BlockSide, origin: [usize; 3], size: [usize; 3], block: Block) {
+  let starting_index = mesh.vertices.len() as u32;
+  mesh.indices.extend(WINDING_ORDER.map(|winding_index| starting_index + winding_index));
+
+  for vec in side.get_face_offset_vectors() { //Stretching the unit face keeps the winding order the same.
+    let position = [0, 1, 2].map(|i| origin[i] + vec[i] as usize * size[i]);
+    mesh.vertices.push(ChunkVertex::new(position, side, block));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Instant;
+
+  use itertools::iproduct;
+
+  use crate::world::{block::{Block, BlockSide, BlockSideVisibility}, chunkedterrain::{CHUNK_LENGTH, CHUNK_RANGE, CHUNK_SIZE}};
+
+  use super::{build_mesh, build_greedy_mesh, ChunkMeshData, ChunkVertex};
+
+  //Bumpy ground with a few different blocks, and the sides that face air are visible (including the edges of the chunk).
+  fn test_terrain() -> (Vec<Block>, Vec<BlockSideVisibility>) {
+    let blocks: Vec<Block> = iproduct!(CHUNK_RANGE, CHUNK_RANGE, CHUNK_RANGE).map(|(x, y, z)| {
+      let height = 12 + (x / 8 + z / 8) % 3;
+      if y > height {Block::Air} else if y == height {Block::Grass} else {Block::Stone}
+    }).collect();
+
+    let is_air = |x: i32, y: i32, z: i32| {
+      let range = 0..CHUNK_SIZE as i32;
+      if !range.contains(&x) || !range.contains(&y) || !range.contains(&z) {
+        return true;
+      }
+      blocks[x as usize * CHUNK_SIZE * CHUNK_SIZE + y as usize * CHUNK_SIZE + z as usize] == Block::Air
+    };
+    let vis = iproduct!(CHUNK_RANGE, CHUNK_RANGE, CHUNK_RANGE).zip(blocks.iter()).map(|((x, y, z), block)| {
+      let mut vis = BlockSideVisibility::new(false);
+      if *block != Block::Air {
+        for side_i in 0..6 {
+          let side = BlockSide::try_from(side_i).unwrap();
+          let o = side.get_offset();
+          vis.set_visible(side, is_air(x as i32 + o.x, y as i32 + o.y, z as i32 + o.z));
+        }
+      }
+      vis
+    }).collect();
+    (blocks, vis)
+  }
+
+  //Total area of the quads facing each way for each block.
+  fn area_per_face_type(mesh: &ChunkMeshData) -> Vec<(([i32; 3], u32), u32)> {
+    let mut areas: Vec<(([i32; 3], u32), u32)> = Vec::new();
+    for quad in mesh.vertices.chunks(4) {
+      let (lo, hi) = quad.iter().fold(([u32::MAX; 3], [0; 3]), |(lo, hi), v| {
+        ([0, 1, 2].map(|i| lo[i].min(v.position()[i])), [0, 1, 2].map(|i| hi[i].max(v.position()[i])))
+      });
+      let area = (0..3).map(|i| hi[i] - lo[i]).filter(|l| *l > 0).product();
+      let face_type = (quad[0].side().get_offset().into(), quad[0].block_index());
+      match areas.iter_mut().find(|(t, _)| *t == face_type) {
+        Some((_, total)) => *total += area,
+        None => areas.push((face_type, area)),
+      }
+    }
+    areas.sort_by_key(|(n, _)| *n);
+    areas
+  }
+
+  #[test]
+  fn test_single_block() {
+    let blocks = vec![Block::Stone; CHUNK_LENGTH];
+    let mut vis = vec![BlockSideVisibility::new(false); CHUNK_LENGTH];
+    vis[0] = BlockSideVisibility::new(true);
+
+    let mesh = build_mesh(&blocks, &vis);
+    assert_eq!(mesh.vertices.len(), 24); //4 per face.
+    assert_eq!(mesh.indices.len(), 36); //2 triangles per face.
+    assert!(mesh.indices.iter().all(|i| (*i as usize) < mesh.vertices.len()));
+    assert!(mesh.vertices.iter().all(|v| v.position().iter().all(|p| *p <= 1)));
+  }
+
+  #[test]
+  fn test_one_side() {
+    let blocks = vec![Block::Grass; CHUNK_LENGTH];
+    let mut vis = vec![BlockSideVisibility::new(false); CHUNK_LENGTH];
+    vis[1].set_visible(BlockSide::Above, true); //Block at (0, 0, 1).
+
+    let mesh = build_mesh(&blocks, &vis);
+    assert_eq!(mesh.vertices.len(), 4);
+    for vertex in mesh.vertices.iter() {
+      assert_eq!(vertex.side(), BlockSide::Above);
+      assert_eq!(vertex.position()[1], 1); //Top of the block.
+      assert_eq!(vertex.block_index(), Block::Grass as u32);
+    }
+  }
+
+  #[test]
+  fn test_greedy_flat_surface() {
+    let blocks = vec![Block::Grass; CHUNK_LENGTH];
+    let mut vis = vec![BlockSideVisibility::new(false); CHUNK_LENGTH];
+    for (x, z) in iproduct!(CHUNK_RANGE, CHUNK_RANGE) { //Top of a layer at y = 5.
+      vis[x * CHUNK_SIZE * CHUNK_SIZE + 5 * CHUNK_SIZE + z].set_visible(BlockSide::Above, true);
+    }
+
+    let mesh = build_greedy_mesh(&blocks, &vis);
+    assert_eq!(mesh.vertices.len(), 4); //One big quad.
+    assert!(mesh.vertices.iter().all(|v| v.position()[1] == 6 && v.side() == BlockSide::Above));
+    assert!(mesh.vertices.iter().any(|v| v.position() == [32, 6, 32])); //Reaches the far corner.
+    assert_eq!(area_per_face_type(&mesh), vec![(([0, 1, 0], Block::Grass as u32), (CHUNK_SIZE * CHUNK_SIZE) as u32)]);
+  }
+
+  #[test]
+  fn test_greedy_matches_simple() {
+    let (blocks, vis) = test_terrain();
+    let simple = build_mesh(&blocks, &vis);
+    let greedy = build_greedy_mesh(&blocks, &vis);
+    assert!(greedy.indices.len() < simple.indices.len());
+    assert_eq!(area_per_face_type(&greedy), area_per_face_type(&simple)); //Covers exactly the same faces with the same blocks.
+  }
+
+  #[test]
+  fn test_vertex_packing() {
+    let vertex = ChunkVertex::new([32, 0, 17], BlockSide::Front, Block::Cloud);
+    assert_eq!(vertex.position(), [32, 0, 17]);
+    assert_eq!(vertex.side(), BlockSide::Front);
+    assert_eq!(vertex.block_index(), Block::Cloud as u32);
+    assert_eq!(std::mem::size_of::<ChunkVertex>(), 4);
+  }
+
+  ///Run with `cargo test --release bench_mesh -- --ignored --nocapture`.
+  #[test]
+  #[ignore]
+  fn bench_mesh() {
+    let (blocks, vis) = test_terrain();
+    const RUNS: u32 = 50;
+    for (name, build) in [("simple", build_mesh as fn(_, _) -> _), ("greedy", build_greedy_mesh)] {
+      let start = Instant::now();
+      let mut triangles = 0;
+      for _ in 0..RUNS {
+        triangles = build(&blocks, &vis).indices.len() / 3;
+      }
+      println!("{}: {} triangles, {:?} per chunk", name, triangles, start.elapsed() / RUNS);
+    }
+  }
+}
@@ -0,0 +1,183 @@
+use std::{collections::VecDeque, sync::{Mutex, atomic::{AtomicUsize, Ordering}}, time::Duration};
+
+use super::chunk_state::ChunkStateStage;
+
+const RECENT_TASKS: usize = 256; //How many durations of each kind of task are kept for the average and p95.
+pub const HISTOGRAM_BUCKETS: usize = 12; //Bucket 0 is under 0.125ms and each bucket after is twice as wide. The last one holds anything slower.
+const FIRST_BUCKET_MS: f32 = 0.125;
+
+///The kinds of task the workers do. Heightmaps are part of generating a chunk, but they're timed on their own because there is one per column.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TaskKind {
+  Heightmap,
+  ChunkGen,
+  ChunkVisGen,
+  MeshGen,
+  LodTile
+}
+
+///Counters and timings for the chunk pipeline. Shared by the terrain and the workers through the task queue.
+pub struct PipelineStats {
+  tasks: [TaskStats; TaskKind::ALL.len()]
+}
+
+struct TaskStats {
+  queued: AtomicUsize, //Includes the tasks in the workers' local queues.
+  in_flight: AtomicUsize,
+  timings: Mutex<TaskTimings>
+}
+
+#[derive(Default)]
+struct TaskTimings {
+  finished: u64,
+  recent: VecDeque<Duration>, //Newest at the back.
+  histogram: [u32; HISTOGRAM_BUCKETS] //Every task since the start.
+}
+
+///A copy of the stats for one kind of task, for the debug menu.
+#[derive(Clone, Debug, Default)]
+pub struct TaskStatsSnapshot {
+  pub queued: usize,
+  pub in_flight: usize,
+  pub finished: u64,
+  pub average: Duration, //Over the recent tasks.
+  pub p95: Duration,
+  pub histogram: [f32; HISTOGRAM_BUCKETS]
+}
+
+///A copy of everything shown in the chunk pipeline panel.
+#[derive(Clone, Debug, Default)]
+pub struct PipelineSnapshot {
+  pub tasks: [TaskStatsSnapshot; TaskKind::ALL.len()], //In the order of TaskKind::ALL.
+  pub chunks_per_stage: [usize; ChunkStateStage::ALL.len()] //In the order of ChunkStateStage::ALL.
+}
+
+impl TaskKind {
+  pub const ALL: [TaskKind; 5] = [TaskKind::Heightmap, TaskKind::ChunkGen, TaskKind::ChunkVisGen, TaskKind::MeshGen, TaskKind::LodTile];
+
+  fn index(&self) -> usize {
+    *self as usize
+  }
+}
+
+impl PipelineStats {
+  pub fn new() -> Self {
+    Self {
+      tasks: TaskKind::ALL.map(|_| TaskStats {
+        queued: AtomicUsize::new(0),
+        in_flight: AtomicUsize::new(0),
+        timings: Mutex::new(TaskTimings::default())
+      })
+    }
+  }
+
+  ///Called by the task queue when a task is added.
+  pub fn task_queued(&self, kind: TaskKind) {
+    self.tasks[kind.index()].queued.fetch_add(1, Ordering::Relaxed);
+  }
+
+  ///Called by the task queue when a task is taken by a worker or dropped.
+  pub fn task_dequeued(&self, kind: TaskKind) {
+    self.tasks[kind.index()].queued.fetch_sub(1, Ordering::Relaxed);
+  }
+
+  ///Called by a worker when it starts a task.
+  pub fn start_task(&self, kind: TaskKind) {
+    self.tasks[kind.index()].in_flight.fetch_add(1, Ordering::Relaxed);
+  }
+
+  ///Called by a worker when it finishes a task, with how long it took.
+  pub fn finish_task(&self, kind: TaskKind, duration: Duration) {
+    let stats = &self.tasks[kind.index()];
+    stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+
+    let mut timings = stats.timings.lock().unwrap();
+    timings.finished += 1;
+    if timings.recent.len() == RECENT_TASKS {
+      timings.recent.pop_front();
+    }
+    timings.recent.push_back(duration);
+    timings.histogram[histogram_bucket(duration)] += 1;
+  }
+
+  ///Copies the stats of every kind of task.
+  pub fn snapshot(&self) -> [TaskStatsSnapshot; TaskKind::ALL.len()] {
+    TaskKind::ALL.map(|kind| {
+      let stats = &self.tasks[kind.index()];
+      let timings = stats.timings.lock().unwrap();
+      let mut recent: Vec<Duration> = timings.recent.iter().copied().collect();
+      recent.sort();
+
+      TaskStatsSnapshot {
+        queued: stats.queued.load(Ordering::Relaxed),
+        in_flight: stats.in_flight.load(Ordering::Relaxed),
+        finished: timings.finished,
+        average: if recent.is_empty() {Duration::ZERO} else {recent.iter().sum::<Duration>() / recent.len() as u32},
+        p95: percentile(&recent, 0.95),
+        histogram: timings.histogram.map(|count| count as f32)
+      }
+    })
+  }
+}
+
+///The histogram bucket a task that took `duration` goes in.
+fn histogram_bucket(duration: Duration) -> usize {
+  let ms = duration.as_secs_f32() * 1000.0;
+  if ms < FIRST_BUCKET_MS {
+    0
+  } else {
+    ((ms / FIRST_BUCKET_MS).log2().floor() as usize + 1).min(HISTOGRAM_BUCKETS - 1)
+  }
+}
+
+///The upper limit (in ms) of a histogram bucket, for labelling it.
+pub fn histogram_bucket_limit(bucket: usize) -> f32 {
+  FIRST_BUCKET_MS * (1 << bucket) as f32
+}
+
+///Gets a percentile of sorted durations using the nearest rank.
+fn percentile(sorted: &[Duration], fraction: f32) -> Duration {
+  if sorted.is_empty() {
+    return Duration::ZERO;
+  }
+  let rank = (sorted.len() as f32 * fraction).ceil() as usize;
+  sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::{histogram_bucket, PipelineStats, TaskKind, HISTOGRAM_BUCKETS};
+
+  #[test]
+  fn test_histogram_buckets() {
+    assert_eq!(histogram_bucket(Duration::from_micros(50)), 0);
+    assert_eq!(histogram_bucket(Duration::from_micros(125)), 1);
+    assert_eq!(histogram_bucket(Duration::from_micros(300)), 2);
+    assert_eq!(histogram_bucket(Duration::from_millis(1)), 4);
+    assert_eq!(histogram_bucket(Duration::from_secs(10)), HISTOGRAM_BUCKETS - 1);
+  }
+
+  #[test]
+  fn test_snapshot() {
+    let stats = PipelineStats::new();
+    for ms in 1..=100 {
+      stats.start_task(TaskKind::MeshGen);
+      stats.finish_task(TaskKind::MeshGen, Duration::from_millis(ms));
+    }
+    stats.start_task(TaskKind::MeshGen);
+    for _ in 0..8 {
+      stats.task_queued(TaskKind::MeshGen);
+    }
+    stats.task_dequeued(TaskKind::MeshGen);
+
+    let snapshot = &stats.snapshot()[3];
+    assert_eq!(snapshot.queued, 7);
+    assert_eq!(snapshot.in_flight, 1);
+    assert_eq!(snapshot.finished, 100);
+    assert_eq!(snapshot.average, Duration::from_micros(50500));
+    assert_eq!(snapshot.p95, Duration::from_millis(95));
+    assert_eq!(snapshot.histogram.iter().sum::<f32>(), 100.0);
+  }
+}
@@ -38,6 +38,16 @@ pub struct HitBox {
   pub hi: FPVector
 }
 
+impl HitBox {
+  ///Checks if the hitbox (at the given position) overlaps with a block. Touching the block does not count.
+  pub fn intersects_block(&self, position: &FPVector, block_pos: Vector3<i32>) -> bool {
+    let (lo, hi) = (*position + self.lo, *position + self.hi);
+    let (block_lo, block_hi) = (FPVector::from(block_pos), FPVector::from(block_pos + Vector3::from([1; 3])));
+
+    (0..3).all(|i| lo.inner[i] < block_hi.inner[i] && hi.inner[i] > block_lo.inner[i])
+  }
+}
+
 /// The PlayerPosC struct is now only used for passing positional data to the GPU.
 #[repr(C)] //Repr(c) because this is being sent to GPU.
 #[derive(Debug, Clone, Copy)]
@@ -64,7 +74,7 @@ impl Player {
     // let pos_offset = self.velocity * dt.as_secs_f32(); //To prevent stuttering and lagging on high Hz monitors.
     let pos_offset = Vector3::from([0.0; 3]); //TODO temp.
     let view = Matrix4::look_to_lh(Point3::from_vec(/*self.position.get_dec() + */ pos_offset), rotation.z, rotation.y);
-    let projection = projection(Deg(self.fov), aspect_ratio, 0.1, 400.0); //Very very far far plane.
+    let projection = projection(Deg(self.fov), aspect_ratio, 0.1, 4096.0); //Very very far far plane (so the outer LOD ring can be seen).
 
     projection * view
   }
@@ -87,6 +97,11 @@ impl Player {
     self.position
   }
 
+  ///Checks if the player's hitbox is inside a block.
+  pub fn is_inside_block(&self, block_pos: Vector3<i32>) -> bool {
+    self.hitbox.intersects_block(&self.position, block_pos)
+  }
+
   pub fn get_pos_c(&self) -> PlayerPosC {
     PlayerPosC {
         block_int: Point3::from_vec(self.position.get_int()),
@@ -118,4 +133,22 @@ impl AddAssign<Vector3<f32>> for PlayerPosC {
       self.block_int = self.block_int.zip(added_float, |s, t| s + t.trunc() as i32 + (if t<0.0 {-1} else {0})); //Add integer components.
       self.block_dec = added_float.map(|v| if v<0.0 {v.fract()+1.0} else {v.fract()}); //Add decimal components.
   }
+}
+
+#[cfg(test)]
+mod tests {
+  use cgmath::Vector3;
+
+  use crate::util::FPVector;
+
+  use super::DEFAULT_HITBOX;
+
+  #[test]
+  fn test_hitbox_intersects_block() {
+    let pos = FPVector::from([0.5f32, 10.5, 0.5]);
+    assert!(DEFAULT_HITBOX.intersects_block(&pos, Vector3::new(0, 10, 0))); //Head.
+    assert!(DEFAULT_HITBOX.intersects_block(&pos, Vector3::new(0, 9, 0))); //Feet.
+    assert!(!DEFAULT_HITBOX.intersects_block(&pos, Vector3::new(0, 8, 0))); //Below the feet.
+    assert!(!DEFAULT_HITBOX.intersects_block(&pos, Vector3::new(1, 10, 0))); //Next to the player.
+  }
 }
\ No newline at end of file
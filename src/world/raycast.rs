@@ -0,0 +1,130 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::util::FPVector;
+
+use super::block::BlockSide;
+
+///Information about the block that a ray hit.
+#[derive(Clone, Copy, Debug)]
+pub struct RaycastHit {
+  pub block_pos: Vector3<i32>,
+  pub side: BlockSide, //The side of the block that the ray entered through.
+  pub distance: f32
+}
+
+/**
+  Steps a ray through the block grid using a voxel DDA (see "A Fast Voxel Traversal Algorithm" by Amanatides and Woo).
+
+  `is_hit` is called for every block the ray passes through (excluding the block containing the origin) and should return
+  `Some(true)` if the ray should stop there, `Some(false)` if the ray can pass through or `None` if the block is not loaded.
+  The ray gives up when it reaches an unloaded block.
+
+  The traversal is done relative to the origin block so precision is not lost far away from the world origin.
+ */
+pub fn cast_ray<F>(origin: FPVector, direction: Vector3<f32>, max_distance: f32, mut is_hit: F) -> Option<RaycastHit>
+  where F: FnMut(Vector3<i32>) -> Option<bool>
+{
+  let dir = direction.normalize();
+  if !dir.x.is_finite() || !dir.y.is_finite() || !dir.z.is_finite() { //Zero length vector.
+    return None;
+  }
+
+  let mut block_pos = origin.get_int();
+  let frac = origin.get_dec();
+
+  let step = dir.map(|v| if v > 0.0 {1i32} else if v < 0.0 {-1} else {0});
+  let t_delta = dir.map(|v| if v != 0.0 {1.0/v.abs()} else {f32::INFINITY}); //Distance along the ray to cross one block.
+  let mut t_max = Vector3::from([0usize, 1, 2].map(|i| { //Distance along the ray to the first block boundary.
+    if dir[i] > 0.0 {
+      (1.0 - frac[i]) / dir[i]
+    } else if dir[i] < 0.0 {
+      frac[i] / -dir[i]
+    } else {
+      f32::INFINITY
+    }
+  }));
+
+  loop {
+    //Step along whichever axis reaches its next boundary first.
+    let axis = if t_max.x <= t_max.y && t_max.x <= t_max.z {
+      0
+    } else if t_max.y <= t_max.z {
+      1
+    } else {
+      2
+    };
+
+    let distance = t_max[axis];
+    if distance > max_distance {
+      return None;
+    }
+
+    block_pos[axis] += step[axis];
+    t_max[axis] += t_delta[axis];
+
+    if is_hit(block_pos)? {
+      let is_positive = step[axis] > 0;
+      let side = match (axis, is_positive) { //The ray enters through the face opposite to the direction it is travelling.
+        (0, true) => BlockSide::Left,
+        (0, false) => BlockSide::Right,
+        (1, true) => BlockSide::Below,
+        (1, false) => BlockSide::Above,
+        (_, true) => BlockSide::Front,
+        (_, false) => BlockSide::Back,
+      };
+
+      return Some(RaycastHit {
+        block_pos,
+        side,
+        distance
+      });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use cgmath::Vector3;
+
+  use crate::{util::FPVector, world::block::BlockSide};
+
+  use super::cast_ray;
+
+  //Solid wall of blocks on the given axis at the given coordinate.
+  fn wall(axis: usize, at: i32) -> impl FnMut(Vector3<i32>) -> Option<bool> {
+    move |pos| Some(pos[axis] == at)
+  }
+
+  #[test]
+  fn test_ray_pos_x() {
+    let hit = cast_ray(FPVector::from([0.5f32, 0.5, 0.5]), Vector3::new(1.0, 0.0, 0.0), 10.0, wall(0, 3)).unwrap();
+    assert_eq!(hit.block_pos, Vector3::new(3, 0, 0));
+    assert_eq!(hit.side, BlockSide::Left);
+    assert!((hit.distance - 2.5).abs() < 1e-5);
+  }
+
+  #[test]
+  fn test_ray_neg_y() {
+    let hit = cast_ray(FPVector::from([0.5f32, 10.25, 0.5]), Vector3::new(0.0, -1.0, 0.0), 20.0, wall(1, -2)).unwrap();
+    assert_eq!(hit.block_pos, Vector3::new(0, -2, 0));
+    assert_eq!(hit.side, BlockSide::Above);
+    assert!((hit.distance - 11.25).abs() < 1e-5);
+  }
+
+  #[test]
+  fn test_ray_diagonal() {
+    let hit = cast_ray(FPVector::from([-0.5f32, 0.5, -0.5]), Vector3::new(0.0, 1.0, -1.0), 10.0, wall(2, -4)).unwrap();
+    assert_eq!(hit.block_pos.z, -4);
+    assert_eq!(hit.side, BlockSide::Back);
+  }
+
+  #[test]
+  fn test_ray_out_of_range() {
+    assert!(cast_ray(FPVector::from([0.5f32, 0.5, 0.5]), Vector3::new(1.0, 0.0, 0.0), 2.0, wall(0, 3)).is_none());
+  }
+
+  #[test]
+  fn test_ray_unloaded() {
+    assert!(cast_ray(FPVector::from([0.5f32, 0.5, 0.5]), Vector3::new(0.0, 0.0, 1.0), 10.0, |_| None).is_none());
+  }
+}
@@ -0,0 +1,170 @@
+use std::collections::{HashSet, VecDeque};
+
+use cgmath::Vector3;
+use itertools::iproduct;
+
+use super::{block::{Block, BlockSide}, chunkedterrain::{CHUNK_LENGTH, CHUNK_SIZE}};
+
+///Which faces of a chunk can be seen from each other through the non-opaque blocks inside it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkConnectivity {
+  bits: u64 //Bit a * 6 + b is set if face a connects to face b.
+}
+
+impl ChunkConnectivity {
+  ///Every face connects to every other one. Used for chunks that haven't been checked yet.
+  pub const ALL: Self = Self { bits: (1 << 36) - 1 };
+  pub const NONE: Self = Self { bits: 0 };
+
+  ///Flood fills the non-opaque blocks in a chunk (in x, then y, then z order) and connects all the faces each area touches.
+  pub fn compute(blocks: &[Block]) -> Self {
+    let mut connectivity = Self::NONE;
+    let mut visited = vec![false; CHUNK_LENGTH];
+    let mut stack = Vec::new();
+
+    for (index, block) in blocks.iter().enumerate() {
+      if visited[index] || !block.is_translucent() {
+        continue;
+      }
+
+      let mut faces = 0u8; //The faces this area touches.
+      visited[index] = true;
+      stack.push(index);
+      while let Some(index) = stack.pop() {
+        let pos = index_to_pos(index);
+        for side_i in 0..6u8 {
+          let side = BlockSide::try_from(side_i).unwrap();
+          let next = pos + side.get_offset();
+          if !(0..CHUNK_SIZE as i32).contains(&next[side_i as usize / 2]) { //Goes out of the chunk on this side.
+            faces |= 1 << side_i;
+            continue;
+          }
+          let next_index = pos_to_index(next);
+          if !visited[next_index] && blocks[next_index].is_translucent() {
+            visited[next_index] = true;
+            stack.push(next_index);
+          }
+        }
+      }
+
+      for (a, b) in iproduct!(0..6, 0..6) {
+        if faces & (1 << a) != 0 && faces & (1 << b) != 0 {
+          connectivity.bits |= 1 << (a * 6 + b);
+        }
+      }
+    }
+
+    connectivity
+  }
+
+  pub fn connects(&self, a: BlockSide, b: BlockSide) -> bool {
+    self.bits & (1 << (a as u64 * 6 + b as u64)) != 0
+  }
+
+  pub fn to_bits(self) -> u64 {
+    self.bits
+  }
+
+  pub fn from_bits(bits: u64) -> Self {
+    Self { bits }
+  }
+}
+
+///Finds the chunks that might be visible from the camera's chunk by searching outwards through faces that connect, like Minecraft does.
+///The search never turns back towards the camera. `get_connectivity` returns None for chunks that can't be entered (like unloaded ones).
+pub fn find_visible_chunks<F>(camera_chunk: [i32; 3], get_connectivity: F) -> HashSet<[i32; 3]>
+  where F: Fn([i32; 3]) -> Option<ChunkConnectivity>
+{
+  let mut visible = HashSet::new();
+  if get_connectivity(camera_chunk).is_none() {
+    return visible;
+  }
+
+  let mut queue = VecDeque::new();
+  visible.insert(camera_chunk);
+  queue.push_back((camera_chunk, None, 0u8)); //(Chunk, face it was entered through, directions travelled so far).
+
+  while let Some((chunk_id, entered_through, directions)) = queue.pop_front() {
+    let connectivity = match get_connectivity(chunk_id) {
+      Some(connectivity) => connectivity,
+      None => continue,
+    };
+
+    for side_i in 0..6u8 {
+      let side = BlockSide::try_from(side_i).unwrap();
+      if directions & (1 << side.opposite() as u8) != 0 { //Don't go back the way we came.
+        continue;
+      }
+      if let Some(entry) = entered_through {
+        if !connectivity.connects(entry, side) {
+          continue;
+        }
+      }
+
+      let next: [i32; 3] = (Vector3::from(chunk_id) + side.get_offset()).into();
+      if visible.contains(&next) || get_connectivity(next).is_none() {
+        continue;
+      }
+      visible.insert(next);
+      queue.push_back((next, Some(side.opposite()), directions | 1 << side_i));
+    }
+  }
+
+  visible
+}
+
+fn index_to_pos(index: usize) -> Vector3<i32> {
+  Vector3::new(index / (CHUNK_SIZE * CHUNK_SIZE), index / CHUNK_SIZE % CHUNK_SIZE, index % CHUNK_SIZE).map(|v| v as i32)
+}
+
+fn pos_to_index(pos: Vector3<i32>) -> usize {
+  pos.x as usize * CHUNK_SIZE * CHUNK_SIZE + pos.y as usize * CHUNK_SIZE + pos.z as usize
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use itertools::iproduct;
+
+  use crate::world::{block::{Block, BlockSide}, chunkedterrain::{CHUNK_LENGTH, CHUNK_RANGE}};
+
+  use super::{ChunkConnectivity, find_visible_chunks};
+
+  #[test]
+  fn test_solid_and_empty() {
+    assert_eq!(ChunkConnectivity::compute(&vec![Block::Stone; CHUNK_LENGTH]), ChunkConnectivity::NONE);
+    assert_eq!(ChunkConnectivity::compute(&vec![Block::Air; CHUNK_LENGTH]), ChunkConnectivity::ALL);
+  }
+
+  #[test]
+  fn test_wall() {
+    //A stone wall across the middle of the chunk along x.
+    let blocks: Vec<Block> = iproduct!(CHUNK_RANGE, CHUNK_RANGE, CHUNK_RANGE)
+      .map(|(x, _, _)| if x == 16 {Block::Stone} else {Block::Air})
+      .collect();
+    let connectivity = ChunkConnectivity::compute(&blocks);
+    assert!(!connectivity.connects(BlockSide::Left, BlockSide::Right));
+    assert!(connectivity.connects(BlockSide::Left, BlockSide::Above));
+    assert!(connectivity.connects(BlockSide::Above, BlockSide::Below));
+    assert!(connectivity.connects(BlockSide::Right, BlockSide::Back));
+  }
+
+  #[test]
+  fn test_search_blocked() {
+    //A row of chunks along x with a solid one in the middle, and a solid layer below the row.
+    let mut chunks = HashMap::new();
+    for x in -3..=3 {
+      chunks.insert([x, 0, 0], if x == 1 {ChunkConnectivity::NONE} else {ChunkConnectivity::ALL});
+      chunks.insert([x, -1, 0], ChunkConnectivity::NONE);
+    }
+    chunks.insert([0, -2, 0], ChunkConnectivity::ALL);
+    let visible = find_visible_chunks([0, 0, 0], |id| chunks.get(&id).copied());
+
+    assert!(visible.contains(&[-3, 0, 0]));
+    assert!(visible.contains(&[1, 0, 0])); //The solid chunk itself can be seen...
+    assert!(!visible.contains(&[2, 0, 0])); //...but not past it.
+    assert!(visible.contains(&[-2, -1, 0])); //The top of the solid layer can be seen...
+    assert!(!visible.contains(&[0, -2, 0])); //...but not under it.
+  }
+}